@@ -1,15 +1,34 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    env,
+    ops::Range,
+    time::Duration,
+};
 
-use chrono::NaiveDate;
-use poem::web::Data;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use futures::{stream::BoxStream, StreamExt};
+use poem::{web::Data, IntoResponse};
 use poem_openapi::{
-    payload::Json,
-    types::{ToJSON, Type},
-    ApiResponse, Object, OpenApi,
+    param::{Header, Path, Query},
+    payload::{EventStream, Json, PlainText},
+    types::{Example, ToJSON, Type},
+    ApiResponse, Enum, Object, OpenApi, ResponseContent, Union,
+};
+use quick_xml::{
+    events::{BytesDecl, BytesText, Event},
+    Writer,
 };
 use reqwest::StatusCode;
+use tokio::sync::broadcast::error::RecvError;
 
-use crate::data::{self, Dataset, SharedDataset};
+use crate::{
+    auth,
+    data::{
+        self, Currency, Dataset, DatasetUpdates, Day, Rounding, SharedDataset, SharedLastUpdate,
+        SharedProvider,
+    },
+};
 
 #[derive(Clone, Copy)]
 pub struct Api;
@@ -18,57 +37,243 @@ pub struct Api;
 struct IndexResponse {
     currencies: Vec<String>,
     timeframe: [NaiveDate; 2],
+    /// Where the rates come from
+    source: String,
+    /// The notice to show when redistributing the rates
+    attribution: String,
+    /// When the dataset was last downloaded or loaded
+    last_updated: DateTime<Utc>,
+}
+
+/// Currency names in the shape of fixer.io's `/symbols`
+#[derive(Object)]
+struct FixerSymbols {
+    success: bool,
+    symbols: HashMap<String, String>,
+}
+
+/// Rates in the shape of fixer.io's `/latest` and `/:date`
+#[derive(Object)]
+struct FixerRates {
+    success: bool,
+    /// Only present for historical rates
+    #[oai(skip_serializing_if_is_none)]
+    historical: Option<bool>,
+    base: String,
+    date: NaiveDate,
+    /// Unavailable rates are left out, fixer.io has no `null` rates
+    rates: HashMap<String, f64>,
+}
+
+/// An error in the shape of fixer.io's, along with fixer.io's error code
+#[derive(Object)]
+struct FixerError {
+    success: bool,
+    error: FixerErrorInfo,
+}
+
+#[derive(Object)]
+struct FixerErrorInfo {
+    code: u16,
+    #[oai(rename = "type")]
+    kind: String,
+    info: String,
+}
+
+#[derive(ApiResponse)]
+enum FixerResponse {
+    #[oai(status = 200)]
+    Ok(Json<FixerRates>),
+    #[oai(status = 404)]
+    DateNotInDataset(Json<FixerError>),
+    #[oai(status = 404)]
+    CurrenciesNotFound(Json<FixerError>),
+}
+
+impl FixerResponse {
+    fn error(code: u16, kind: &str, info: String) -> Json<FixerError> {
+        Json(FixerError {
+            success: false,
+            error: FixerErrorInfo {
+                code,
+                kind: kind.to_string(),
+                info,
+            },
+        })
+    }
+
+    /// Reshapes the rates built for `rates`
+    fn from_rates(rates: Rates, historical: bool) -> Self {
+        FixerResponse::Ok(Json(FixerRates {
+            success: true,
+            historical: historical.then_some(true),
+            base: rates.base,
+            date: rates.date,
+            rates: rates
+                .rates
+                .entries()
+                .into_iter()
+                .filter_map(|(currency, rate)| Some((currency.to_string(), rate?)))
+                .collect(),
+        }))
+    }
+}
+
+#[derive(Object)]
+struct CurrencyMetadata {
+    code: String,
+    name: Option<String>,
+    symbol: Option<String>,
+}
+
+/// Bounds the work per request, also for `to` lists that are not validated
+/// with `max_items` because they come from the query string
+const MAX_CURRENCIES: usize = 100;
+
+/// More than `MAX_CURRENCIES` were requested
+struct TooManyCurrencies(usize);
+
+impl From<TooManyCurrencies> for poem::Error {
+    fn from(TooManyCurrencies(requested): TooManyCurrencies) -> Self {
+        poem::Error::from_string(
+            format!(
+                "At most {} currencies can be requested, got {}",
+                MAX_CURRENCIES, requested
+            ),
+            StatusCode::BAD_REQUEST,
+        )
+    }
 }
 
 #[derive(Debug)]
 struct Conversion {
-    from: &'static str,
+    from: Currency,
     to: Vec<String>,
 }
 
 impl Default for Conversion {
     fn default() -> Self {
         Self {
-            from: data::EUR,
+            from: Currency::from(data::EUR),
             to: Vec::new(),
         }
     }
 }
 
+/// Selects the base currency and the currencies to return
 #[derive(Object, Clone, Debug)]
+#[oai(example)]
 struct ConversionParams {
-    #[oai(validator(pattern = "^([A-Z]{3})$"))]
+    /// Currency the rates are relative to, EUR by default
+    #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))]
     from: Option<String>,
-    #[oai(validator(pattern = "^([A-Z]{3})$"))]
+    /// Currencies to return, all of them by default
+    #[oai(validator(max_items = 100, pattern = "^\\s*([A-Za-z]{3})\\s*$"))]
     to: Option<Vec<String>>,
 }
 
-#[derive(Object)]
+impl Example for ConversionParams {
+    fn example() -> Self {
+        Self {
+            from: Some("USD".to_string()),
+            to: Some(vec!["GBP".to_string(), "JPY".to_string()]),
+        }
+    }
+}
+
+/// Rates for a single date, the latest one unless `date` is given
+#[derive(Object, Default)]
+#[oai(example)]
 struct RatesRequest {
+    /// Falls back to the previous day with rates, unless `strict`
     date: Option<NaiveDate>,
+    /// When provided, the rates are multiplied by this amount
+    amount: Option<f64>,
+    /// Number of decimal places to round the rates to
+    #[oai(validator(maximum(value = "15")))]
+    places: Option<u8>,
+    /// When set, a date without rates is an error instead of falling back to the previous day
+    strict: Option<bool>,
+    /// Returns the rates as an array ordered like `to`, or by currency code
+    ordered: Option<bool>,
+    /// Leaves out currencies without a rate instead of returning `null`
+    drop_nulls: Option<bool>,
+    #[oai(flatten)]
+    conversion: Option<ConversionParams>,
+}
+
+impl Example for RatesRequest {
+    fn example() -> Self {
+        Self {
+            date: NaiveDate::from_ymd_opt(2024, 1, 2),
+            amount: Some(100.0),
+            places: Some(4),
+            strict: Some(false),
+            ordered: Some(false),
+            drop_nulls: Some(false),
+            conversion: Some(ConversionParams::example()),
+        }
+    }
+}
+
+#[derive(Object)]
+struct DatesRequest {
+    /// Every date is resolved on its own, falling back to the previous day with rates
+    dates: Vec<NaiveDate>,
+    /// Number of decimal places to round the rates to
+    #[oai(validator(maximum(value = "15")))]
+    places: Option<u8>,
+    /// Returns the rates as an array ordered like `to`, or by currency code
+    ordered: Option<bool>,
+    /// Leaves out currencies without a rate instead of returning `null`
+    drop_nulls: Option<bool>,
     #[oai(flatten)]
     conversion: Option<ConversionParams>,
 }
 
 impl Conversion {
+    /// Resolves the requested conversion, from EUR to every currency when
+    /// there is none
+    fn resolve(
+        params: Option<&ConversionParams>,
+        dataset: &Dataset,
+    ) -> Result<Result<Self, CurrenciesNotFound>, TooManyCurrencies> {
+        match params {
+            Some(params) => Conversion::from_params(params, dataset),
+            None => Ok(Ok(Conversion::default())),
+        }
+    }
+
+    /// The base currency exists, but has no rates on the days in question
+    fn unavailable_base(&self) -> CurrenciesNotFound {
+        CurrenciesNotFound {
+            currencies_not_found: vec![self.from.to_string()],
+        }
+    }
+
     fn from_params(
         params: &ConversionParams,
         dataset: &Dataset,
-    ) -> Result<Self, CurrenciesNotFound> {
-        Ok(Self {
-            from: match params.from.as_ref() {
+    ) -> Result<Result<Self, CurrenciesNotFound>, TooManyCurrencies> {
+        let requested = params.to.as_ref().map_or(0, Vec::len);
+        if requested > MAX_CURRENCIES {
+            return Err(TooManyCurrencies(requested));
+        }
+
+        Ok(Ok(Self {
+            from: match params.from.as_deref().map(normalize_code) {
                 Some(from) => match dataset.from(&from) {
                     // If we have a matching currency, return it
                     Some(from) => from,
                     // If not, return an error
                     None => {
-                        return Err(CurrenciesNotFound {
-                            currencies_not_found: vec![from.clone()],
-                        })
+                        return Ok(Err(CurrenciesNotFound {
+                            currencies_not_found: vec![from],
+                        }))
                     }
                 },
                 // If no currency has been provided, use EUR
-                None => data::EUR,
+                None => Currency::from(data::EUR),
             },
             // If no `conversion.to` was specified, return an empty `Vec` → all currencies
             to: {
@@ -76,47 +281,447 @@ impl Conversion {
                     .to
                     .as_ref()
                     .unwrap_or(&Vec::new())
-                    .clone()
-                    .into_iter()
-                    .partition(|c| dataset.currencies.binary_search(&c.as_str()).is_ok());
+                    .iter()
+                    .map(|c| normalize_code(c))
+                    .partition(|c| dataset.index_of(c).is_some());
 
                 if !not_found.is_empty() {
-                    return Err(CurrenciesNotFound {
+                    return Ok(Err(CurrenciesNotFound {
                         currencies_not_found: not_found,
-                    });
+                    }));
                 }
 
-                to
+                // Keep the first occurrence, so the order stays as requested
+                let mut seen = HashSet::new();
+                to.into_iter().filter(|c| seen.insert(c.clone())).collect()
             },
-        })
+        }))
     }
 }
 
+/// Rates of a single day
 #[derive(Object)]
+#[oai(example)]
 struct Rates {
+    /// The day the rates were published on
+    date: NaiveDate,
+    /// The date from the request, which differs from `date`
+    /// when there were no rates that day
+    #[oai(skip_serializing_if_is_none)]
+    requested_date: Option<NaiveDate>,
+    /// How many days `date` is before `requested_date` (negative when after it),
+    /// only present when they differ
+    #[oai(skip_serializing_if_is_none)]
+    days_offset: Option<i64>,
+    /// The currency all the rates are relative to
+    base: String,
+    /// How much of each currency one unit of `base` buys, `null` when unavailable that day
+    rates: RateList,
+}
+
+impl Example for Rates {
+    fn example() -> Self {
+        Self {
+            date: NaiveDate::from_ymd_opt(2024, 1, 2).unwrap_or_default(),
+            requested_date: None,
+            days_offset: None,
+            base: "USD".to_string(),
+            rates: RateList::Map(HashMap::from([
+                ("GBP".to_string(), Some(0.7897)),
+                ("JPY".to_string(), Some(141.95)),
+            ])),
+        }
+    }
+}
+
+#[derive(Object)]
+struct CurrencyRate {
+    currency: String,
+    rate: Option<f64>,
+}
+
+/// Rates by currency code, or an array of them when `ordered` was requested
+#[derive(Union)]
+#[oai(one_of)]
+enum RateList {
+    Map(HashMap<String, Option<f64>>),
+    Ordered(Vec<CurrencyRate>),
+}
+
+impl RateList {
+    /// Keeps the rates as a map, unless `ordered`. Then they are ordered
+    /// as requested in `to`, or by currency code when no `to` was given
+    fn new(rates: HashMap<String, Option<f64>>, to: &[String], ordered: bool) -> Self {
+        if !ordered {
+            return RateList::Map(rates);
+        }
+
+        let mut rates = rates
+            .into_iter()
+            .map(|(currency, rate)| CurrencyRate { currency, rate })
+            .collect::<Vec<_>>();
+
+        if to.is_empty() {
+            rates.sort_by(|a, b| a.currency.cmp(&b.currency));
+        } else {
+            rates.sort_by_key(|rate| to.iter().position(|c| *c == rate.currency));
+        }
+
+        RateList::Ordered(rates)
+    }
+
+    /// Returns the rates in the order they are serialized in
+    fn entries(&self) -> Vec<(&str, Option<f64>)> {
+        match self {
+            RateList::Map(rates) => {
+                let mut entries = rates
+                    .iter()
+                    .map(|(currency, rate)| (currency.as_str(), *rate))
+                    .collect::<Vec<_>>();
+                entries.sort_by_key(|(currency, _)| *currency);
+
+                entries
+            }
+            RateList::Ordered(rates) => rates
+                .iter()
+                .map(|rate| (rate.currency.as_str(), rate.rate))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Object)]
+struct ConvertRequest {
+    #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))]
+    from: String,
+    #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))]
+    to: String,
+    amount: f64,
+    date: Option<NaiveDate>,
+}
+
+#[derive(Object)]
+struct Converted {
+    date: NaiveDate,
+    rate: f64,
+    result: f64,
+}
+
+#[derive(Object)]
+struct Pair {
     date: NaiveDate,
-    rates: HashMap<String, Option<f64>>,
+    base: String,
+    quote: String,
+    /// How much of `quote` one unit of `base` buys
+    rate: f64,
 }
 
 #[derive(Object)]
+struct DecimalRates {
+    date: NaiveDate,
+    base: String,
+    rates: HashMap<String, Option<String>>,
+}
+
+/// Periods over which the timeframe rates are averaged. Every period is
+/// represented by its last trading day. Periods cut off by the edges
+/// of the timeframe are averaged over the days inside the timeframe only
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[oai(rename_all = "lowercase")]
+enum Granularity {
+    #[default]
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Granularity {
+    /// Identifies the period the date belongs to
+    fn period(self, date: NaiveDate) -> (i32, u32) {
+        match self {
+            Granularity::Daily => (date.year(), date.ordinal()),
+            Granularity::Weekly => (date.iso_week().year(), date.iso_week().week()),
+            Granularity::Monthly => (date.year(), date.month()),
+        }
+    }
+}
+
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[oai(rename_all = "lowercase")]
+enum TimeframeMode {
+    #[default]
+    Rates,
+    /// Percentage change from the previous day the currency had a rate on
+    Returns,
+}
+
+impl TimeframeMode {
+    fn apply(self, days: Vec<Cow<'_, Day>>) -> Vec<Cow<'_, Day>> {
+        match self {
+            TimeframeMode::Rates => days,
+            TimeframeMode::Returns => {
+                let mut previous = Vec::<Option<f64>>::new();
+
+                days.into_iter()
+                    .map(|day| {
+                        previous.resize(day.rates.len(), None);
+
+                        // The first rate of every currency has nothing to compare to
+                        let rates = day
+                            .rates
+                            .iter()
+                            .zip(previous.iter_mut())
+                            .map(|(rate, previous)| {
+                                let rate = (*rate)?;
+                                let change = previous.map(|p| (rate / p - 1.0) * 100.0);
+                                *previous = Some(rate);
+                                change
+                            })
+                            .collect();
+
+                        Cow::Owned(Day {
+                            date: day.date,
+                            rates,
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Divides every rate by the currency's first rate in the timeframe and
+/// multiplies it by `base`. Returns the day each currency was anchored to
+fn rebase(days: Vec<Cow<'_, Day>>, base: f64) -> (Vec<Cow<'_, Day>>, Vec<Option<NaiveDate>>) {
+    let mut anchors = Vec::<Option<(NaiveDate, f64)>>::new();
+
+    let days = days
+        .into_iter()
+        .map(|day| {
+            anchors.resize(day.rates.len(), None);
+
+            // Currencies without a rate on the first day anchor to their first rate
+            let rates = day
+                .rates
+                .iter()
+                .zip(anchors.iter_mut())
+                .map(|(rate, anchor)| {
+                    let rate = (*rate)?;
+                    let (_, anchor_rate) = *anchor.get_or_insert((day.date, rate));
+                    Some(rate / anchor_rate * base)
+                })
+                .collect();
+
+            Cow::Owned(Day {
+                date: day.date,
+                rates,
+            })
+        })
+        .collect();
+
+    let anchors = anchors
+        .into_iter()
+        .map(|anchor| anchor.map(|(date, _)| date))
+        .collect();
+
+    (days, anchors)
+}
+
+#[derive(Object, Default)]
 struct TimeframeRequest {
     timeframe: [Option<NaiveDate>; 2],
+    /// When provided, the rates of every day are multiplied by this amount,
+    /// which leaves `rebase` and `mode` unaffected
+    #[oai(validator(minimum(value = "0", exclusive)))]
+    amount: Option<f64>,
+    /// Number of decimal places to round the rates to
+    #[oai(validator(maximum(value = "15")))]
+    places: Option<u8>,
+    /// Averages the rates over weeks or months instead of returning every day
+    granularity: Option<Granularity>,
+    /// Returns the day-over-day percentage change instead of the rates
+    mode: Option<TimeframeMode>,
+    /// Rebases every currency so that its first rate in the timeframe equals this, e.g. 100
+    #[oai(validator(minimum(value = "0", exclusive), maximum(value = "1000000")))]
+    rebase: Option<f64>,
+    /// Number of days to skip
+    offset: Option<usize>,
+    /// Maximum number of days to return
+    limit: Option<usize>,
+    /// Returns the rates as an array ordered like `to`, or by currency code
+    ordered: Option<bool>,
+    /// Leaves out currencies without a rate instead of returning `null`
+    drop_nulls: Option<bool>,
+    /// Also returns the dates in the timeframe the ECB published no rates on
+    missing_dates: Option<bool>,
     #[oai(flatten)]
     conversion: Option<ConversionParams>,
 }
 
+/// Rates of every day (or week, or month) in a timeframe
 #[derive(Object)]
+#[oai(example)]
 struct Timeframe {
+    /// The first and the last day with rates in the requested timeframe
     timeframe: [NaiveDate; 2],
+    /// Number of days in the timeframe, regardless of pagination
+    total: usize,
+    /// Oldest first
     rates: Vec<Rates>,
+    /// With `rebase`, the first day each currency had a rate on in the timeframe
+    #[oai(skip_serializing_if_is_none)]
+    anchors: Option<HashMap<String, NaiveDate>>,
+    /// With `missing_dates`, every date in the timeframe without rates, weekends included
+    #[oai(skip_serializing_if_is_none)]
+    missing_dates: Option<Vec<NaiveDate>>,
+}
+
+impl Example for Timeframe {
+    fn example() -> Self {
+        let first = Rates::example();
+        let last = Rates {
+            date: first.date + chrono::Duration::days(1),
+            rates: RateList::Map(HashMap::from([
+                ("GBP".to_string(), Some(0.7912)),
+                ("JPY".to_string(), Some(143.12)),
+            ])),
+            ..Rates::example()
+        };
+
+        Self {
+            timeframe: [first.date, last.date],
+            total: 2,
+            rates: vec![first, last],
+            anchors: None,
+            missing_dates: None,
+        }
+    }
+}
+
+#[derive(Object, Default)]
+struct CurrencyStats {
+    min: Option<f64>,
+    min_date: Option<NaiveDate>,
+    max: Option<f64>,
+    max_date: Option<NaiveDate>,
+    average: Option<f64>,
+    /// Sample standard deviation of the rates, needs at least two days
+    std_dev: Option<f64>,
+    /// Standard deviation of the daily log returns, annualized over 252 trading days
+    volatility: Option<f64>,
+    /// Number of days the currency had a rate on
+    count: usize,
+}
+
+/// Welford's online algorithm, so that the rates only have to be gone through once
+#[derive(Default)]
+struct RunningVariance {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningVariance {
+    fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn std_dev(&self) -> Option<f64> {
+        (self.count > 1).then(|| (self.m2 / (self.count - 1) as f64).sqrt())
+    }
+}
+
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+impl CurrencyStats {
+    fn from_series(series: impl Iterator<Item = (NaiveDate, Option<f64>)>) -> Self {
+        let mut stats = Self::default();
+        let mut rates = RunningVariance::default();
+        let mut returns = RunningVariance::default();
+        let mut previous: Option<f64> = None;
+
+        // Days when the currency was not available are skipped
+        for (date, rate) in series.filter_map(|(date, rate)| Some((date, rate?))) {
+            if stats.min.is_none_or(|min| rate < min) {
+                stats.min = Some(rate);
+                stats.min_date = Some(date);
+            }
+
+            if stats.max.is_none_or(|max| rate > max) {
+                stats.max = Some(rate);
+                stats.max_date = Some(date);
+            }
+
+            rates.push(rate);
+            if let Some(previous) = previous {
+                returns.push((rate / previous).ln());
+            }
+            previous = Some(rate);
+        }
+
+        stats.count = rates.count;
+        if rates.count > 0 {
+            stats.average = Some(rates.mean);
+        }
+        stats.std_dev = rates.std_dev();
+        stats.volatility = returns
+            .std_dev()
+            .map(|std_dev| std_dev * TRADING_DAYS_PER_YEAR.sqrt());
+
+        stats
+    }
+}
+
+#[derive(Object)]
+struct StatsRequest {
+    timeframe: [Option<NaiveDate>; 2],
+    #[oai(flatten)]
+    conversion: Option<ConversionParams>,
+}
+
+#[derive(Object)]
+struct TimeframeStats {
+    timeframe: [NaiveDate; 2],
+    base: String,
+    stats: HashMap<String, CurrencyStats>,
+}
+
+#[derive(Object)]
+struct FluctuationRequest {
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    #[oai(flatten)]
+    conversion: Option<ConversionParams>,
+}
+
+#[derive(Object)]
+struct Fluctuation {
+    start_rate: Option<f64>,
+    end_rate: Option<f64>,
+    change: Option<f64>,
+    /// Change relative to the start rate, in percent
+    change_pct: Option<f64>,
+}
+
+#[derive(Object)]
+struct Fluctuations {
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    base: String,
+    rates: HashMap<String, Fluctuation>,
 }
 
 #[derive(ApiResponse)]
 enum RatesResponse<T: Send + Type + ToJSON> {
     #[oai(status = 200)]
     Ok(Json<T>),
+    #[oai(status = 400)]
+    DateNotAvailable(Json<DateNotAvailable>),
     #[oai(status = 404)]
-    CurrenciesNotFound(Json<CurrenciesNotFound>),
+    NotFound(Json<NotFound>),
 }
 
 impl<T> From<CurrenciesNotFound> for RatesResponse<T>
@@ -124,189 +729,1860 @@ where
     T: Send + Type + ToJSON,
 {
     fn from(value: CurrenciesNotFound) -> Self {
-        RatesResponse::CurrenciesNotFound(Json(value))
+        RatesResponse::NotFound(Json(NotFound::Currencies(value)))
     }
 }
 
-#[derive(Object)]
-struct CurrenciesNotFound {
-    #[oai(skip_serializing_if_is_empty)]
-    currencies_not_found: Vec<String>,
+impl<T> From<DateNotAvailable> for RatesResponse<T>
+where
+    T: Send + Type + ToJSON,
+{
+    fn from(value: DateNotAvailable) -> Self {
+        RatesResponse::DateNotAvailable(Json(value))
+    }
 }
 
-impl Api {
-    fn no_rates() -> poem::Error {
-        poem::Error::from_string("No rates available", StatusCode::INTERNAL_SERVER_ERROR)
-    }
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[oai(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Json,
+    Csv,
+    Xml,
 }
 
-#[OpenApi]
-impl Api {
-    /// Returns the list of available currencies and the timeframe of the dataset
-    #[oai(path = "/", method = "get")]
-    async fn index(&self, dataset: Data<&SharedDataset>) -> poem::Result<Json<IndexResponse>> {
-        let dataset = dataset.read().await;
+/// The quality `Accept` gives to any of the media types. The most specific
+/// matching range wins, so `application/json;q=0` beats `*/*`
+fn accept_quality(accept: &str, media_types: &[&str]) -> f32 {
+    let mut best: Option<(u8, f32)> = None;
 
-        match dataset.timeframe() {
-            Some([first, last]) => Ok(Json(IndexResponse {
-                currencies: dataset
-                    .currencies
-                    .iter()
-                    .map(ToString::to_string)
-                    .collect::<Vec<_>>(),
-                timeframe: [first, last],
-            })),
-            None => Err(Api::no_rates()),
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let range = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let specificity = media_types
+            .iter()
+            .filter_map(|media_type| match range.as_str() {
+                "*/*" => Some(0),
+                range if range == *media_type => Some(2),
+                range => range
+                    .strip_suffix("/*")
+                    .filter(|kind| media_type.split('/').next() == Some(kind))
+                    .map(|_| 1),
+            })
+            .max();
+
+        if let Some(specificity) = specificity {
+            let is_better = best.is_none_or(|(best_specificity, best_quality)| {
+                (specificity, quality) > (best_specificity, best_quality)
+            });
+
+            if is_better {
+                best = Some((specificity, quality));
+            }
         }
     }
 
-    /// Returns the exchange rates for the given date
-    #[oai(path = "/rates", method = "post")]
-    async fn rates(
-        &self,
-        dataset: Data<&SharedDataset>,
-        req: Json<Option<RatesRequest>>,
-    ) -> poem::Result<RatesResponse<Rates>> {
-        let dataset = dataset.read().await;
-
-        // Try to extract the date from the request
-        let index = match req.as_ref().and_then(|r| r.date) {
-            // Find the index of the day if provided
-            Some(date) => dataset
-                .days
-                .binary_search_by_key(&date, |day| day.date)
-                // We are using `.checked_sub` because if the dataset
-                // is empty, we would run into an underflow
-                .unwrap_or_else(|e| e.checked_sub(1).unwrap_or_default()),
+    best.map_or(0.0, |(_, quality)| quality)
+}
 
-            // Otherwise, use the latest day
-            None => dataset.days.len().checked_sub(1).unwrap_or_default(),
-        };
+impl Format {
+    /// Picks the output format, the `format` parameter takes precedence over `Accept`.
+    /// JSON is returned unless `Accept` ranks CSV or XML strictly higher
+    fn negotiate(format: Option<Format>, accept: Option<&str>) -> Self {
+        if let Some(format) = format {
+            return format;
+        }
 
-        let conversion = match req
-            .as_ref()
-            .and_then(|r| r.conversion.as_ref())
-            .map(|c| Conversion::from_params(&c, &dataset))
-        {
-            // Supplied → use it
-            Some(Ok(conversion)) => conversion,
-            // Error → return it
-            Some(Err(e)) => return Ok(e.into()),
-            // None → use default
-            None => Conversion::default(),
+        let Some(accept) = accept else {
+            return Format::Json;
         };
 
-        let day = dataset.days.get(index).ok_or_else(Api::no_rates)?.clone();
+        let json = accept_quality(accept, &["application/json"]);
+        let csv = accept_quality(accept, &["text/csv"]);
+        let xml = accept_quality(accept, &["application/xml", "text/xml"]);
 
-        // It actually makes sense to clone the rates here because returning
-        // the values from the API is going to consume them anyway
-        let day = match day.convert(conversion.from, dataset.currencies) {
-            Some(converted) => converted,
-            None => {
-                // We have validated this before but the base currency might
-                // not be available for the requested date
-                return Ok(CurrenciesNotFound {
-                    currencies_not_found: vec![conversion.from.to_string()],
-                }
-                .into());
-            }
-        };
+        if csv > json && csv >= xml {
+            Format::Csv
+        } else if xml > json {
+            Format::Xml
+        } else {
+            Format::Json
+        }
+    }
+}
 
-        let mut rates = day.to_hashmap(dataset.currencies);
+trait ToCsv {
+    fn to_csv(&self) -> String;
+}
 
-        if !conversion.to.is_empty() {
-            rates = rates
-                .into_iter()
-                .filter(|(c, _)| conversion.to.contains(c))
-                .collect::<HashMap<_, _>>();
+impl ToCsv for Rates {
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("currency,rate\n");
+        for (currency, rate) in self.rates.entries() {
+            // Unavailable rates are left empty
+            let rate = rate.map(|r| r.to_string()).unwrap_or_default();
+            csv.push_str(&format!("{},{}\n", currency, rate));
         }
 
-        Ok(RatesResponse::Ok(Json(Rates {
-            date: day.date,
-            rates,
-        })))
+        csv
     }
+}
+
+impl ToCsv for Timeframe {
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("date,currency,rate\n");
+        for day in self.rates.iter() {
+            for (currency, rate) in day.rates.entries() {
+                let rate = rate.map(|r| r.to_string()).unwrap_or_default();
+                csv.push_str(&format!("{},{},{}\n", day.date, currency, rate));
+            }
+        }
 
-    #[oai(path = "/rates", method = "get")]
-    async fn rates_(&self, dataset: Data<&SharedDataset>) -> poem::Result<RatesResponse<Rates>> {
-        self.rates(dataset, Json(None)).await
+        csv
     }
+}
 
-    #[oai(path = "/rates/timeframe", method = "post")]
-    async fn timeframe(
-        &self,
+trait ToXml {
+    fn to_xml(&self) -> String;
+}
+
+/// Writes the rates as `<rates>` with a `<rate>` for every currency,
+/// unavailable rates are left empty
+fn write_rates(writer: &mut Writer<Vec<u8>>, rates: &Rates) -> quick_xml::Result<()> {
+    let date = rates.date.to_string();
+    let requested_date = rates.requested_date.map(|date| date.to_string());
+    let days_offset = rates.days_offset.map(|offset| offset.to_string());
+
+    let mut element = writer
+        .create_element("rates")
+        .with_attribute(("date", date.as_str()))
+        .with_attribute(("base", rates.base.as_str()));
+    if let Some(requested_date) = requested_date.as_deref() {
+        element = element.with_attribute(("requested_date", requested_date));
+    }
+    if let Some(days_offset) = days_offset.as_deref() {
+        element = element.with_attribute(("days_offset", days_offset));
+    }
+
+    element.write_inner_content(|writer| {
+        for (currency, rate) in rates.rates.entries() {
+            let element = writer
+                .create_element("rate")
+                .with_attribute(("currency", currency));
+            match rate {
+                Some(rate) => element.write_text_content(BytesText::new(&rate.to_string()))?,
+                None => element.write_empty()?,
+            };
+        }
+
+        Ok::<_, quick_xml::Error>(())
+    })?;
+
+    Ok(())
+}
+
+/// Writes the XML declaration and whatever `write` adds after it
+fn xml_document(write: impl FnOnce(&mut Writer<Vec<u8>>) -> quick_xml::Result<()>) -> String {
+    let mut writer = Writer::new(Vec::new());
+
+    writer
+        .write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .and_then(|_| write(&mut writer))
+        .expect("writing to a `Vec` cannot fail");
+
+    String::from_utf8(writer.into_inner()).expect("the XML is built from strings")
+}
+
+impl ToXml for Rates {
+    fn to_xml(&self) -> String {
+        xml_document(|writer| write_rates(writer, self))
+    }
+}
+
+impl ToXml for Timeframe {
+    fn to_xml(&self) -> String {
+        let [start, end] = self.timeframe.map(|date| date.to_string());
+        let total = self.total.to_string();
+
+        xml_document(|writer| {
+            writer
+                .create_element("timeframe")
+                .with_attribute(("start", start.as_str()))
+                .with_attribute(("end", end.as_str()))
+                .with_attribute(("total", total.as_str()))
+                .write_inner_content(|writer| {
+                    for rates in self.rates.iter() {
+                        write_rates(writer, rates)?;
+                    }
+
+                    if let Some(anchors) = self.anchors.as_ref() {
+                        let mut anchors = anchors.iter().collect::<Vec<_>>();
+                        anchors.sort();
+
+                        writer
+                            .create_element("anchors")
+                            .write_inner_content(|writer| {
+                                for (currency, date) in anchors {
+                                    writer
+                                        .create_element("anchor")
+                                        .with_attribute(("currency", currency.as_str()))
+                                        .with_attribute(("date", date.to_string().as_str()))
+                                        .write_empty()?;
+                                }
+
+                                Ok::<_, quick_xml::Error>(())
+                            })?;
+                    }
+
+                    if let Some(missing_dates) = self.missing_dates.as_ref() {
+                        writer
+                            .create_element("missing")
+                            .write_inner_content(|writer| {
+                                for date in missing_dates {
+                                    writer
+                                        .create_element("date")
+                                        .write_text_content(BytesText::new(&date.to_string()))?;
+                                }
+
+                                Ok::<_, quick_xml::Error>(())
+                            })?;
+                    }
+
+                    Ok::<_, quick_xml::Error>(())
+                })?;
+
+            Ok(())
+        })
+    }
+}
+
+#[derive(ResponseContent)]
+enum Formatted<T: Send + Type + ToJSON> {
+    Json(Json<T>),
+    #[oai(content_type = "text/csv")]
+    Csv(PlainText<String>),
+    #[oai(content_type = "application/xml")]
+    Xml(PlainText<String>),
+}
+
+impl<T> Formatted<T>
+where
+    T: Send + Type + ToJSON + ToCsv + ToXml,
+{
+    fn new(value: T, format: Format) -> Self {
+        match format {
+            Format::Json => Formatted::Json(Json(value)),
+            Format::Csv => Formatted::Csv(PlainText(value.to_csv())),
+            Format::Xml => Formatted::Xml(PlainText(value.to_xml())),
+        }
+    }
+}
+
+/// Same as `RatesResponse` but the rates can also be returned in other formats
+#[derive(ApiResponse)]
+enum FormattedResponse<T: Send + Type + ToJSON> {
+    #[oai(status = 200)]
+    Ok(Formatted<T>),
+    #[oai(status = 400)]
+    DateNotAvailable(Json<DateNotAvailable>),
+    #[oai(status = 404)]
+    NotFound(Json<NotFound>),
+}
+
+impl<T> From<CurrenciesNotFound> for FormattedResponse<T>
+where
+    T: Send + Type + ToJSON,
+{
+    fn from(value: CurrenciesNotFound) -> Self {
+        FormattedResponse::NotFound(Json(NotFound::Currencies(value)))
+    }
+}
+
+impl<T> From<DateNotAvailable> for FormattedResponse<T>
+where
+    T: Send + Type + ToJSON,
+{
+    fn from(value: DateNotAvailable) -> Self {
+        FormattedResponse::DateNotAvailable(Json(value))
+    }
+}
+
+#[derive(Object)]
+struct CurrenciesNotFound {
+    #[oai(skip_serializing_if_is_empty)]
+    currencies_not_found: Vec<String>,
+}
+
+/// The requested date is before the first day with rates
+#[derive(Object)]
+struct DateNotInDataset {
+    date: NaiveDate,
+    /// The first and the last day with rates
+    timeframe: [NaiveDate; 2],
+}
+
+/// Either some of the currencies, or the date, are not in the dataset
+#[derive(Union)]
+#[oai(one_of)]
+enum NotFound {
+    Currencies(CurrenciesNotFound),
+    Date(DateNotInDataset),
+}
+
+/// The requested date has no rates, along with the closest dates that do
+#[derive(Object)]
+struct DateNotAvailable {
+    date: NaiveDate,
+    previous: Option<NaiveDate>,
+    next: Option<NaiveDate>,
+}
+
+#[derive(Object)]
+struct Health {
+    last_date: Option<NaiveDate>,
+    days: usize,
+    update_overdue: bool,
+}
+
+#[derive(ApiResponse)]
+enum HealthResponse {
+    #[oai(status = 200)]
+    Healthy(Json<Health>),
+    #[oai(status = 503)]
+    Unhealthy(Json<Health>),
+}
+
+#[derive(Object)]
+struct Refreshed {
+    timeframe: [NaiveDate; 2],
+    days: usize,
+}
+
+#[derive(ApiResponse)]
+enum RefreshResponse {
+    #[oai(status = 200)]
+    Refreshed(Json<Refreshed>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+enum SpotRateResponse {
+    #[oai(status = 200)]
+    Ok(PlainText<String>),
+    #[oai(status = 404)]
+    CurrenciesNotFound(PlainText<String>),
+}
+
+/// What the latest dataset update changed
+#[derive(Object)]
+struct LastUpdate {
+    at: DateTime<Utc>,
+    succeeded: bool,
+    /// Dates that were not in the dataset before the update
+    added_dates: Vec<NaiveDate>,
+    #[oai(skip_serializing_if_is_none)]
+    error: Option<String>,
+}
+
+#[derive(ApiResponse)]
+enum LastUpdateResponse {
+    #[oai(status = 200)]
+    Ok(Json<LastUpdate>),
+    #[oai(status = 401)]
+    Unauthorized,
+    /// There was no update since the service started
+    #[oai(status = 404)]
+    NoUpdateYet,
+}
+
+/// Why `Api::day_for` or `Api::timeframe_days` found no days
+enum NoDay {
+    NoRates,
+    BeforeDataset {
+        date: NaiveDate,
+        timeframe: [NaiveDate; 2],
+    },
+    StartAfterEnd {
+        start: NaiveDate,
+        end: NaiveDate,
+    },
+    OutsideDataset {
+        requested: [Option<NaiveDate>; 2],
+        timeframe: [NaiveDate; 2],
+    },
+}
+
+impl From<NoDay> for poem::Error {
+    fn from(value: NoDay) -> Self {
+        match value {
+            NoDay::NoRates => Api::no_rates(),
+            // The `NotFound` response of the handlers, so that it is in their spec
+            NoDay::BeforeDataset { date, timeframe } => poem::Error::from_response(
+                Json(NotFound::Date(DateNotInDataset { date, timeframe }))
+                    .with_status(StatusCode::NOT_FOUND)
+                    .into_response(),
+            ),
+            NoDay::StartAfterEnd { start, end } => poem::Error::from_string(
+                format!("start must be before end, got {} and {}", start, end),
+                StatusCode::BAD_REQUEST,
+            ),
+            NoDay::OutsideDataset {
+                requested,
+                timeframe,
+            } => {
+                let requested = match requested {
+                    [Some(start), Some(end)] => format!("{} to {}", start, end),
+                    [Some(start), None] => format!("{} onwards", start),
+                    [None, Some(end)] => format!("up to {}", end),
+                    [None, None] => "any date".to_string(),
+                };
+
+                Api::not_in_dataset(requested, timeframe)
+            }
+        }
+    }
+}
+
+/// Why `Api::build_rates` built no rates
+enum NoRates {
+    NoDay(NoDay),
+    DateNotAvailable(DateNotAvailable),
+    CurrenciesNotFound(CurrenciesNotFound),
+    TooManyCurrencies(TooManyCurrencies),
+    AmountOutOfRange,
+}
+
+/// Plain-text errors, for handlers without a typed response for the reason
+impl From<NoRates> for poem::Error {
+    fn from(value: NoRates) -> Self {
+        match value {
+            NoRates::NoDay(e) => e.into(),
+            NoRates::DateNotAvailable(e) => poem::Error::from_string(
+                format!("No rates for {}", e.date),
+                StatusCode::BAD_REQUEST,
+            ),
+            NoRates::CurrenciesNotFound(e) => poem::Error::from_string(
+                format!("Unknown currencies: {}", e.currencies_not_found.join(",")),
+                StatusCode::NOT_FOUND,
+            ),
+            NoRates::TooManyCurrencies(e) => e.into(),
+            NoRates::AmountOutOfRange => Api::amount_out_of_range(),
+        }
+    }
+}
+
+/// Currency codes are matched in their canonical form, so ` usd` is just `USD`
+fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+/// Rounds the rate to the given number of decimal places
+fn round(rate: f64, places: u8) -> f64 {
+    let factor = 10f64.powi(places.into());
+    let scaled = rate * factor;
+
+    // Rates this large have no decimal places to round anyway
+    if !scaled.is_finite() {
+        return rate;
+    }
+
+    scaled.round() / factor
+}
+
+/// Multiplies a rate by the requested amount, `None` when the result is too
+/// large for a float (which could not be serialized either)
+fn times_amount(rate: f64, amount: f64) -> Option<f64> {
+    Some(rate * amount).filter(|result| result.is_finite())
+}
+
+impl Api {
+    fn no_rates() -> poem::Error {
+        poem::Error::from_string("No rates available", StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn amount_out_of_range() -> poem::Error {
+        poem::Error::from_string("Amount is out of range", StatusCode::BAD_REQUEST)
+    }
+
+    fn rates_out_of_range() -> poem::Error {
+        poem::Error::from_string(
+            "The resulting rates are out of range",
+            StatusCode::BAD_REQUEST,
+        )
+    }
+
+    /// Checks the `Authorization` header against `ADMIN_TOKEN`, with or without
+    /// the `Bearer` prefix. Without `ADMIN_TOKEN`, nobody is an admin
+    fn is_admin(authorization: Option<&str>) -> bool {
+        let Ok(token) = env::var("ADMIN_TOKEN") else {
+            return false;
+        };
+
+        match authorization {
+            Some(authorization) => {
+                let supplied = authorization
+                    .strip_prefix("Bearer ")
+                    .unwrap_or(authorization);
+
+                !token.is_empty() && auth::tokens_match(supplied, &token)
+            }
+            None => false,
+        }
+    }
+
+    /// The dataset does not go back (or forward) far enough
+    fn not_in_dataset(requested: impl std::fmt::Display, timeframe: [NaiveDate; 2]) -> poem::Error {
+        let [first, last] = timeframe;
+
+        poem::Error::from_string(
+            format!(
+                "No rates for {}, rates are available from {} to {}",
+                requested, first, last
+            ),
+            StatusCode::NOT_FOUND,
+        )
+    }
+
+    /// Finds the day for the given date, falling back to the previous available
+    /// day, or the latest day when no date is given. Dates before the first day
+    /// are not found
+    fn day_for(dataset: &Dataset, date: Option<NaiveDate>) -> Result<&Day, NoDay> {
+        let timeframe = dataset.timeframe().ok_or(NoDay::NoRates)?;
+
+        let index = match date {
+            Some(date) => dataset
+                .index_for_date(date, Rounding::Previous)
+                .ok_or(NoDay::BeforeDataset { date, timeframe })?,
+            None => dataset.days.len() - 1,
+        };
+
+        Ok(&dataset.days[index])
+    }
+
+    /// Same as `day_for`, but when `strict`, a date without rates is an error
+    /// instead of falling back to another day
+    fn strict_day_for(
+        dataset: &Dataset,
+        date: Option<NaiveDate>,
+        strict: bool,
+    ) -> Result<Result<&Day, DateNotAvailable>, NoDay> {
+        match date {
+            Some(date) if strict => match dataset.index_for_date(date, Rounding::Exact) {
+                Some(index) => Ok(Ok(&dataset.days[index])),
+                None => {
+                    let closest = |rounding| {
+                        dataset
+                            .index_for_date(date, rounding)
+                            .map(|index| dataset.days[index].date)
+                    };
+
+                    Ok(Err(DateNotAvailable {
+                        date,
+                        previous: closest(Rounding::Previous),
+                        next: closest(Rounding::Next),
+                    }))
+                }
+            },
+            _ => Api::day_for(dataset, date).map(Ok),
+        }
+    }
+
+    /// Number of days between the requested and the resolved date,
+    /// `None` when nothing was requested or no substitution happened
+    fn days_offset(requested: Option<NaiveDate>, resolved: NaiveDate) -> Option<i64> {
+        requested
+            .map(|requested| (requested - resolved).num_days())
+            .filter(|offset| *offset != 0)
+    }
+
+    /// Makes sure the timeframe does not end before it starts
+    fn check_timeframe(timeframe: [Option<NaiveDate>; 2]) -> Result<(), NoDay> {
+        match timeframe {
+            [Some(start), Some(end)] if start > end => Err(NoDay::StartAfterEnd { start, end }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolves the requested timeframe to the matching range of day indices,
+    /// failing when no day falls inside it
+    fn timeframe_range(
+        dataset: &Dataset,
+        timeframe: [Option<NaiveDate>; 2],
+    ) -> Result<Range<usize>, NoDay> {
+        Api::check_timeframe(timeframe)?;
+
+        let [start, end] = timeframe;
+        let range = dataset.timeframe_range(start, end);
+
+        if range.is_empty() {
+            return Err(match dataset.timeframe() {
+                Some(available) => NoDay::OutsideDataset {
+                    requested: timeframe,
+                    timeframe: available,
+                },
+                None => NoDay::NoRates,
+            });
+        }
+
+        Ok(range)
+    }
+
+    /// Resolves the requested timeframe to the matching slice of days
+    fn timeframe_days(
+        dataset: &Dataset,
+        timeframe: [Option<NaiveDate>; 2],
+    ) -> Result<&[Day], NoDay> {
+        let range = Api::timeframe_range(dataset, timeframe)?;
+
+        dataset.days.get(range).ok_or(NoDay::NoRates)
+    }
+
+    /// Returns the dates in the requested timeframe without one of `days`, which
+    /// `Api::timeframe_days` resolved from the same timeframe. Open ends, and
+    /// ends outside the dataset, stop at the dataset's timeframe, so that neither
+    /// the time before the ECB published rates nor the future count as missing
+    fn missing_dates(
+        dataset: &Dataset,
+        timeframe: [Option<NaiveDate>; 2],
+        days: &[Day],
+    ) -> Vec<NaiveDate> {
+        let Some([first, last]) = dataset.timeframe() else {
+            return Vec::new();
+        };
+
+        let start = timeframe[0].map_or(first, |start| start.max(first));
+        let end = timeframe[1].map_or(last, |end| end.min(last));
+        let available = days.iter().map(|day| day.date).collect::<HashSet<_>>();
+
+        start
+            .iter_days()
+            .take_while(|date| *date <= end)
+            .filter(|date| !available.contains(date))
+            .collect()
+    }
+
+    /// Builds the rates for `rates` and the fixer.io endpoints, or tells why
+    /// there are none
+    fn build_rates(dataset: &Dataset, req: Option<&RatesRequest>) -> Result<Rates, NoRates> {
+        let conversion = match Conversion::resolve(req.and_then(|r| r.conversion.as_ref()), dataset)
+            .map_err(NoRates::TooManyCurrencies)?
+        {
+            Ok(conversion) => conversion,
+            Err(e) => return Err(NoRates::CurrenciesNotFound(e)),
+        };
+
+        let date = req.and_then(|r| r.date);
+        let strict = req.and_then(|r| r.strict).unwrap_or(false);
+
+        let day = match Api::strict_day_for(dataset, date, strict) {
+            Ok(Ok(day)) => day,
+            Ok(Err(e)) => return Err(NoRates::DateNotAvailable(e)),
+            Err(e) => return Err(NoRates::NoDay(e)),
+        };
+
+        let day = match day.convert(&conversion.from, dataset) {
+            Some(converted) => converted,
+            None => {
+                // We have validated this before but the base currency might
+                // not be available for the requested date
+                return Err(NoRates::CurrenciesNotFound(conversion.unavailable_base()));
+            }
+        };
+
+        let mut rates = day.to_hashmap(&dataset.currencies);
+
+        if !conversion.to.is_empty() {
+            rates = rates
+                .into_iter()
+                .filter(|(c, _)| conversion.to.contains(c))
+                .collect::<HashMap<_, _>>();
+        }
+
+        if req.and_then(|r| r.drop_nulls).unwrap_or(false) {
+            rates.retain(|_, rate| rate.is_some());
+        }
+
+        if let Some(amount) = req.and_then(|r| r.amount) {
+            // Unavailable rates stay `None`
+            for rate in rates.values_mut().filter_map(Option::as_mut) {
+                *rate = times_amount(*rate, amount).ok_or(NoRates::AmountOutOfRange)?;
+            }
+        }
+
+        if let Some(places) = req.and_then(|r| r.places) {
+            for rate in rates.values_mut() {
+                *rate = rate.map(|r| round(r, places));
+            }
+        }
+
+        let ordered = req.and_then(|r| r.ordered).unwrap_or(false);
+
+        Ok(Rates {
+            date: day.date,
+            requested_date: date,
+            days_offset: Api::days_offset(date, day.date),
+            base: conversion.from.to_string(),
+            rates: RateList::new(rates, &conversion.to, ordered),
+        })
+    }
+
+    /// Returns the EUR-based rates of the latest day in the dataset
+    fn latest_rates(dataset: &Dataset) -> Option<Rates> {
+        let day = dataset.days.last()?;
+
+        Some(Rates {
+            date: day.date,
+            requested_date: None,
+            days_offset: None,
+            base: data::EUR.to_string(),
+            rates: RateList::Map(day.to_hashmap(&dataset.currencies)),
+        })
+    }
+
+    async fn fixer_rates(
+        &self,
+        dataset: Data<&SharedDataset>,
+        date: Option<NaiveDate>,
+        base: Option<String>,
+        symbols: Option<String>,
+    ) -> poem::Result<FixerResponse> {
+        // fixer.io takes the currencies as a single comma-separated list
+        let symbols = symbols.map(|symbols| {
+            symbols
+                .split(',')
+                .map(str::trim)
+                .filter(|symbol| !symbol.is_empty())
+                .map(str::to_string)
+                .collect()
+        });
+
+        let req = RatesRequest {
+            date,
+            conversion: Some(ConversionParams {
+                from: base,
+                to: symbols,
+            }),
+            ..Default::default()
+        };
+
+        let dataset = data::snapshot(&dataset).await;
+        let response = match Api::build_rates(&dataset, Some(&req)) {
+            Ok(rates) => FixerResponse::from_rates(rates, date.is_some()),
+            Err(NoRates::NoDay(NoDay::BeforeDataset { date, timeframe })) => {
+                let [first, last] = timeframe;
+
+                FixerResponse::DateNotInDataset(FixerResponse::error(
+                    302,
+                    "invalid_date",
+                    format!(
+                        "No rates for {}, rates are available from {} to {}",
+                        date, first, last
+                    ),
+                ))
+            }
+            Err(NoRates::CurrenciesNotFound(e)) => {
+                FixerResponse::CurrenciesNotFound(FixerResponse::error(
+                    202,
+                    "invalid_currency_codes",
+                    format!("Unknown currencies: {}", e.currencies_not_found.join(",")),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(response)
+    }
+}
+
+#[OpenApi]
+impl Api {
+    /// Returns the list of available currencies and the timeframe of the dataset
+    #[oai(path = "/", method = "get", operation_id = "index")]
+    async fn index(
+        &self,
+        dataset: Data<&SharedDataset>,
+        provider: Data<&SharedProvider>,
+        /// Lists EUR first and the rest alphabetically, instead of all alphabetically
+        eur_first: Query<Option<bool>>,
+    ) -> poem::Result<Json<IndexResponse>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let mut currencies = dataset
+            .currencies
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        if eur_first.unwrap_or(false) {
+            // The sort is stable, so the rest stays alphabetical
+            currencies.sort_by_key(|c| c != data::EUR);
+        }
+
+        match dataset.timeframe() {
+            Some([first, last]) => Ok(Json(IndexResponse {
+                currencies,
+                timeframe: [first, last],
+                source: provider.source(),
+                attribution: data::ATTRIBUTION.to_string(),
+                last_updated: dataset.loaded_at,
+            })),
+            None => Err(Api::no_rates()),
+        }
+    }
+
+    /// Reports whether a reasonably fresh dataset is loaded
+    #[oai(path = "/health", method = "get", operation_id = "health")]
+    async fn health(&self, dataset: Data<&SharedDataset>) -> HealthResponse {
+        let max_age = env::var("HEALTH_MAX_AGE")
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            // ECB does not publish on weekends and holidays, which can add up
+            // to four days in a row around Easter
+            .unwrap_or(4);
+
+        let dataset = data::snapshot(&dataset).await;
+        let last_date = dataset.days.last().map(|day| day.date);
+
+        let update_overdue = match last_date {
+            Some(date) => (data::today() - date).num_days() > max_age,
+            None => true,
+        };
+
+        let health = Health {
+            last_date,
+            days: dataset.days.len(),
+            update_overdue,
+        };
+
+        if update_overdue {
+            HealthResponse::Unhealthy(Json(health))
+        } else {
+            HealthResponse::Healthy(Json(health))
+        }
+    }
+
+    /// Downloads the dataset right away instead of waiting for the scheduled update.
+    /// Requires `ADMIN_TOKEN` in the `Authorization` header
+    #[oai(
+        path = "/admin/refresh",
+        method = "post",
+        operation_id = "admin_refresh"
+    )]
+    async fn admin_refresh(
+        &self,
+        dataset: Data<&SharedDataset>,
+        updates: Data<&DatasetUpdates>,
+        provider: Data<&SharedProvider>,
+        last_update: Data<&SharedLastUpdate>,
+        #[oai(name = "Authorization")] authorization: Header<Option<String>>,
+    ) -> poem::Result<RefreshResponse> {
+        if !Api::is_admin(authorization.as_deref()) {
+            return Ok(RefreshResponse::Unauthorized);
+        }
+
+        data::update_dataset(provider.as_ref(), &dataset, &updates, &last_update)
+            .await
+            .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::BAD_GATEWAY))?;
+
+        let dataset = data::snapshot(&dataset).await;
+        let timeframe = dataset.timeframe().ok_or_else(Api::no_rates)?;
+
+        Ok(RefreshResponse::Refreshed(Json(Refreshed {
+            timeframe,
+            days: dataset.days.len(),
+        })))
+    }
+
+    /// Reports when the dataset was last updated, whether that worked, and which days it added
+    #[oai(
+        path = "/admin/last-update",
+        method = "get",
+        operation_id = "admin_last_update"
+    )]
+    async fn admin_last_update(
+        &self,
+        last_update: Data<&SharedLastUpdate>,
+        #[oai(name = "Authorization")] authorization: Header<Option<String>>,
+    ) -> LastUpdateResponse {
+        if !Api::is_admin(authorization.as_deref()) {
+            return LastUpdateResponse::Unauthorized;
+        }
+
+        match last_update.read().await.as_ref() {
+            Some(update) => LastUpdateResponse::Ok(Json(LastUpdate {
+                at: update.at,
+                succeeded: update.error.is_none(),
+                added_dates: update.added.clone(),
+                error: update.error.clone(),
+            })),
+            None => LastUpdateResponse::NoUpdateYet,
+        }
+    }
+
+    /// Returns the available currencies along with their names and symbols
+    #[oai(path = "/currencies", method = "get", operation_id = "currencies")]
+    async fn currencies(&self, dataset: Data<&SharedDataset>) -> Json<Vec<CurrencyMetadata>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        Json(
+            dataset
+                .currencies
+                .iter()
+                .map(|code| {
+                    // Unknown currencies are still listed, just without a name
+                    let info = data::currency_info(code);
+
+                    CurrencyMetadata {
+                        code: code.to_string(),
+                        name: info.map(|(name, _)| name.to_string()),
+                        symbol: info.map(|(_, symbol)| symbol.to_string()),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the names of the available currencies like fixer.io does
+    #[oai(path = "/symbols", method = "get", operation_id = "symbols")]
+    async fn symbols(&self, dataset: Data<&SharedDataset>) -> Json<FixerSymbols> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let symbols = dataset
+            .currencies
+            .iter()
+            .map(|code| {
+                // fixer.io always has a name, so unknown currencies go by their code
+                let name = data::currency_info(code).map_or(&**code, |(name, _)| name);
+
+                (code.to_string(), name.to_string())
+            })
+            .collect();
+
+        Json(FixerSymbols {
+            success: true,
+            symbols,
+        })
+    }
+
+    /// Returns every date with published rates, oldest first
+    #[oai(path = "/dates", method = "get", operation_id = "dates")]
+    async fn dates(
+        &self,
+        dataset: Data<&SharedDataset>,
+        offset: Query<Option<usize>>,
+        limit: Query<Option<usize>>,
+    ) -> Json<Vec<NaiveDate>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        Json(
+            dataset
+                .days
+                .iter()
+                .skip(offset.unwrap_or(0))
+                .take(limit.unwrap_or(usize::MAX))
+                .map(|day| day.date)
+                .collect(),
+        )
+    }
+
+    /// Returns the exchange rates for the given date
+    #[oai(path = "/rates", method = "post", operation_id = "rates")]
+    async fn rates(
+        &self,
+        dataset: Data<&SharedDataset>,
+        req: Json<Option<RatesRequest>>,
+        format: Query<Option<Format>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> poem::Result<FormattedResponse<Rates>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let rates = match Api::build_rates(&dataset, req.as_ref()) {
+            Ok(rates) => rates,
+            Err(NoRates::DateNotAvailable(e)) => return Ok(e.into()),
+            Err(NoRates::CurrenciesNotFound(e)) => return Ok(e.into()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(FormattedResponse::Ok(Formatted::new(
+            rates,
+            Format::negotiate(format.0, accept.as_deref()),
+        )))
+    }
+
+    #[oai(path = "/rates", method = "get", operation_id = "rates_get")]
+    async fn rates_(
+        &self,
+        dataset: Data<&SharedDataset>,
+        format: Query<Option<Format>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> poem::Result<FormattedResponse<Rates>> {
+        self.rates(dataset, Json(None), format, accept).await
+    }
+
+    /// Returns the exchange rates for each of the given dates
+    #[oai(path = "/rates/dates", method = "post", operation_id = "rates_dates")]
+    async fn rates_dates(
+        &self,
+        dataset: Data<&SharedDataset>,
+        req: Json<DatesRequest>,
+    ) -> poem::Result<RatesResponse<Vec<Rates>>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let conversion = match Conversion::resolve(req.conversion.as_ref(), &dataset)? {
+            Ok(conversion) => conversion,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let mut rates = Vec::with_capacity(req.dates.len());
+
+        for &date in req.dates.iter() {
+            let day = Api::day_for(&dataset, Some(date))?;
+
+            let Some(day) = day.convert(&conversion.from, &dataset) else {
+                // The base currency might not be available for some of the dates
+                return Ok(conversion.unavailable_base().into());
+            };
+
+            let mut day_rates = day.to_hashmap(&dataset.currencies);
+
+            if !conversion.to.is_empty() {
+                day_rates.retain(|c, _| conversion.to.contains(c));
+            }
+
+            if req.drop_nulls.unwrap_or(false) {
+                day_rates.retain(|_, rate| rate.is_some());
+            }
+
+            if let Some(places) = req.places {
+                for rate in day_rates.values_mut() {
+                    *rate = rate.map(|r| round(r, places));
+                }
+            }
+
+            rates.push(Rates {
+                date: day.date,
+                requested_date: Some(date),
+                days_offset: Api::days_offset(Some(date), day.date),
+                base: conversion.from.to_string(),
+                rates: RateList::new(day_rates, &conversion.to, req.ordered.unwrap_or(false)),
+            });
+        }
+
+        Ok(RatesResponse::Ok(Json(rates)))
+    }
+
+    /// Returns the exchange rates for the given date as decimal strings
+    #[oai(
+        path = "/rates/decimal",
+        method = "post",
+        operation_id = "rates_decimal"
+    )]
+    async fn rates_decimal(
+        &self,
+        dataset: Data<&SharedDataset>,
+        req: Json<Option<RatesRequest>>,
+    ) -> poem::Result<RatesResponse<DecimalRates>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let conversion = match Conversion::resolve(
+            req.as_ref().and_then(|r| r.conversion.as_ref()),
+            &dataset,
+        )? {
+            Ok(conversion) => conversion,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let date = req.as_ref().and_then(|r| r.date);
+        let strict = req.as_ref().and_then(|r| r.strict).unwrap_or(false);
+
+        let day = match Api::strict_day_for(&dataset, date, strict)? {
+            Ok(day) => day,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let Some(rates) = day.convert_decimal(&conversion.from, &dataset) else {
+            // The base currency might not be available for the requested date
+            return Ok(conversion.unavailable_base().into());
+        };
+
+        let amount = match req.as_ref().and_then(|r| r.amount) {
+            Some(amount) => Some(data::to_decimal(amount).ok_or_else(Api::amount_out_of_range)?),
+            None => None,
+        };
+        let places = req.as_ref().and_then(|r| r.places);
+
+        let mut decimal_rates = HashMap::new();
+        for (currency, rate) in dataset.currencies.iter().zip(rates) {
+            if !conversion.to.is_empty() && !conversion.to.iter().any(|to| **to == **currency) {
+                continue;
+            }
+
+            let rate = match (rate, amount) {
+                (Some(rate), Some(amount)) => Some(
+                    rate.checked_mul(amount)
+                        .ok_or_else(Api::amount_out_of_range)?,
+                ),
+                (rate, _) => rate,
+            };
+
+            let rate = rate.map(|r| match places {
+                Some(places) => r.round_dp(places.into()),
+                None => r,
+            });
+
+            decimal_rates.insert(
+                currency.to_string(),
+                rate.map(|r| r.normalize().to_string()),
+            );
+        }
+
+        Ok(RatesResponse::Ok(Json(DecimalRates {
+            date: day.date,
+            base: conversion.from.to_string(),
+            rates: decimal_rates,
+        })))
+    }
+
+    /// Returns the latest exchange rates converted using the query string parameters
+    #[oai(path = "/rates/latest", method = "get", operation_id = "latest")]
+    async fn latest(
+        &self,
+        dataset: Data<&SharedDataset>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] from: Query<Option<String>>,
+        to: Query<Option<Vec<String>>>,
+        format: Query<Option<Format>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> poem::Result<FormattedResponse<Rates>> {
+        let req = RatesRequest {
+            conversion: Some(ConversionParams {
+                from: from.0,
+                to: to.0,
+            }),
+            ..Default::default()
+        };
+
+        self.rates(dataset, Json(Some(req)), format, accept).await
+    }
+
+    /// Returns the latest exchange rates with the base currency from the path
+    #[oai(path = "/rates/:base", method = "get", operation_id = "rates_base")]
+    async fn rates_base(
+        &self,
+        dataset: Data<&SharedDataset>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] base: Path<String>,
+        to: Query<Option<Vec<String>>>,
+        format: Query<Option<Format>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> poem::Result<FormattedResponse<Rates>> {
+        self.latest(dataset, Query(Some(base.0)), to, format, accept)
+            .await
+    }
+
+    /// Returns the exchange rates for the date with the base currency from the path
+    #[oai(
+        path = "/rates/:base/:date",
+        method = "get",
+        operation_id = "rates_base_date"
+    )]
+    async fn rates_base_date(
+        &self,
+        dataset: Data<&SharedDataset>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] base: Path<String>,
+        date: Path<NaiveDate>,
+        to: Query<Option<Vec<String>>>,
+        format: Query<Option<Format>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> poem::Result<FormattedResponse<Rates>> {
+        let req = RatesRequest {
+            date: Some(date.0),
+            conversion: Some(ConversionParams {
+                from: Some(base.0),
+                to: to.0,
+            }),
+            ..Default::default()
+        };
+
+        self.rates(dataset, Json(Some(req)), format, accept).await
+    }
+
+    /// Returns the latest exchange rates like fixer.io's `/latest` does
+    #[oai(
+        path = "/compat/fixer/latest",
+        method = "get",
+        operation_id = "fixer_latest"
+    )]
+    async fn fixer_latest(
+        &self,
+        dataset: Data<&SharedDataset>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] base: Query<Option<String>>,
+        symbols: Query<Option<String>>,
+    ) -> poem::Result<FixerResponse> {
+        self.fixer_rates(dataset, None, base.0, symbols.0).await
+    }
+
+    /// Returns the exchange rates for the date like fixer.io's `/:date` does
+    #[oai(
+        path = "/compat/fixer/:date",
+        method = "get",
+        operation_id = "fixer_historical"
+    )]
+    async fn fixer_historical(
+        &self,
+        dataset: Data<&SharedDataset>,
+        date: Path<NaiveDate>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] base: Query<Option<String>>,
+        symbols: Query<Option<String>>,
+    ) -> poem::Result<FixerResponse> {
+        self.fixer_rates(dataset, Some(date.0), base.0, symbols.0)
+            .await
+    }
+
+    /// Converts an amount from one currency to another
+    #[oai(path = "/convert", method = "post", operation_id = "convert")]
+    async fn convert(
+        &self,
+        dataset: Data<&SharedDataset>,
+        req: Json<ConvertRequest>,
+    ) -> poem::Result<RatesResponse<Converted>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let (from_code, to_code) = (normalize_code(&req.from), normalize_code(&req.to));
+
+        let (from, to) = match (dataset.from(&from_code), dataset.from(&to_code)) {
+            (Some(from), Some(to)) => (from, to),
+            (from, to) => {
+                let mut currencies_not_found = [(from, from_code), (to, to_code)]
+                    .into_iter()
+                    .filter(|(found, _)| found.is_none())
+                    .map(|(_, c)| c)
+                    .collect::<Vec<_>>();
+                currencies_not_found.dedup();
+
+                return Ok(CurrenciesNotFound {
+                    currencies_not_found,
+                }
+                .into());
+            }
+        };
+
+        let day = Api::day_for(&dataset, req.date)?;
+
+        // Both currencies need to be available on the given day
+        let mut currencies_not_found = [&from, &to]
+            .into_iter()
+            .filter(|c| day.rate(c, &dataset).is_none())
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        currencies_not_found.dedup();
+
+        let rate = day
+            .convert(&from, &dataset)
+            .and_then(|day| day.rate(&to, &dataset));
+
+        match rate {
+            Some(rate) if currencies_not_found.is_empty() => {
+                Ok(RatesResponse::Ok(Json(Converted {
+                    date: day.date,
+                    rate,
+                    result: times_amount(rate, req.amount).ok_or_else(Api::amount_out_of_range)?,
+                })))
+            }
+            _ => Ok(CurrenciesNotFound {
+                currencies_not_found,
+            }
+            .into()),
+        }
+    }
+
+    /// Returns the rate of a single currency pair, the latest one unless a date is given
+    #[oai(path = "/rates/pair", method = "get", operation_id = "pair")]
+    async fn pair(
+        &self,
+        dataset: Data<&SharedDataset>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] base: Query<String>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] quote: Query<String>,
+        date: Query<Option<NaiveDate>>,
+    ) -> poem::Result<RatesResponse<Pair>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let (base, quote) = (normalize_code(&base), normalize_code(&quote));
+        let day = Api::day_for(&dataset, date.0)?;
+
+        // Both rates are EUR-based, so we only need to divide them
+        match (day.rate(&base, &dataset), day.rate(&quote, &dataset)) {
+            (Some(base_rate), Some(quote_rate)) => Ok(RatesResponse::Ok(Json(Pair {
+                date: day.date,
+                base,
+                quote,
+                rate: quote_rate / base_rate,
+            }))),
+            (base_rate, quote_rate) => {
+                let mut currencies_not_found = [(base_rate, base), (quote_rate, quote)]
+                    .into_iter()
+                    .filter(|(rate, _)| rate.is_none())
+                    .map(|(_, c)| c)
+                    .collect::<Vec<_>>();
+                currencies_not_found.dedup();
+
+                Ok(CurrenciesNotFound {
+                    currencies_not_found,
+                }
+                .into())
+            }
+        }
+    }
+
+    /// Returns just the latest rate of a currency pair as plain text, for scripts
+    #[oai(path = "/rate", method = "get", operation_id = "spot_rate")]
+    async fn spot_rate(
+        &self,
+        dataset: Data<&SharedDataset>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] from: Query<String>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] to: Query<String>,
+        #[oai(validator(maximum(value = "15")))] places: Query<Option<u8>>,
+    ) -> poem::Result<SpotRateResponse> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let (from, to) = (normalize_code(&from), normalize_code(&to));
+        let day = dataset.days.last().ok_or_else(Api::no_rates)?;
+
+        match (day.rate(&from, &dataset), day.rate(&to, &dataset)) {
+            (Some(from_rate), Some(to_rate)) => {
+                let rate = to_rate / from_rate;
+                let rate = places.0.map_or(rate, |places| round(rate, places));
+
+                Ok(SpotRateResponse::Ok(PlainText(rate.to_string())))
+            }
+            (from_rate, _) => {
+                let currency = if from_rate.is_none() { from } else { to };
+
+                Ok(SpotRateResponse::CurrenciesNotFound(PlainText(format!(
+                    "Currency not found: {}",
+                    currency
+                ))))
+            }
+        }
+    }
+
+    /// Streams the latest rates on connect and then after every dataset update
+    #[oai(path = "/rates/stream", method = "get", operation_id = "stream")]
+    async fn stream(
+        &self,
+        dataset: Data<&SharedDataset>,
+        updates: Data<&DatasetUpdates>,
+    ) -> EventStream<BoxStream<'static, Rates>> {
+        let dataset = SharedDataset::clone(&dataset);
+
+        let updates = futures::stream::unfold(updates.subscribe(), |mut receiver| async move {
+            match receiver.recv().await {
+                // If we lagged behind, we still only need to send the latest rates once
+                Ok(()) | Err(RecvError::Lagged(_)) => Some(((), receiver)),
+                // The scheduler is gone, so there will be no more updates
+                Err(RecvError::Closed) => None,
+            }
+        });
+
+        // Emit once right away and then on every update. When the client
+        // disconnects, the stream (and with it the receiver) simply gets dropped
+        let stream = futures::stream::once(async {})
+            .chain(updates)
+            .filter_map(move |()| {
+                let dataset = dataset.clone();
+                async move { Api::latest_rates(&*data::snapshot(&dataset).await) }
+            })
+            .boxed();
+
+        EventStream::new(stream).keep_alive(Duration::from_secs(30))
+    }
+
+    #[oai(path = "/rates/timeframe", method = "post", operation_id = "timeframe")]
+    async fn timeframe(
+        &self,
         dataset: Data<&SharedDataset>,
         req: Json<TimeframeRequest>,
-    ) -> poem::Result<RatesResponse<Timeframe>> {
-        let dataset = dataset.read().await;
-
-        let (start, end) = (
-            req.timeframe[0]
-                .map(|start| {
-                    dataset
-                        .days
-                        .binary_search_by_key(&start, |day| day.date)
-                        // If not found, take the previous day (or the first day)
-                        .unwrap_or_else(|e| e.checked_sub(1).unwrap_or_default())
-                })
-                // Otherwise, take the very first day
-                .unwrap_or(0),
-            req.timeframe[1]
-                .map(|end| {
-                    dataset
-                        .days
-                        .binary_search_by_key(&end, |day| day.date)
-                        // If not found, take the next day
-                        .unwrap_or_else(|e| e + 1)
+        format: Query<Option<Format>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> poem::Result<FormattedResponse<Timeframe>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let days = Api::timeframe_days(&dataset, req.timeframe)?;
+
+        let conversion = match Conversion::resolve(req.conversion.as_ref(), &dataset)? {
+            Ok(conversion) => conversion,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let converted = dataset
+            .convert_days(days, &conversion.from)
+            .collect::<Vec<_>>();
+
+        // Before `rebase` and `mode`, which are relative to the (multiplied) rates
+        let converted = match req.amount {
+            Some(amount) => converted
+                .into_iter()
+                .map(|day| {
+                    let mut day = day.into_owned();
+
+                    // Unavailable rates stay `None`
+                    for rate in day.rates.iter_mut().flatten() {
+                        *rate = times_amount(*rate, amount)?;
+                    }
+
+                    Some(Cow::Owned(day))
                 })
-                // Otherwise, take the latest
-                .unwrap_or(dataset.days.len() - 1),
-        );
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(Api::amount_out_of_range)?,
+            None => converted,
+        };
 
-        let days = dataset.days.get(start..end).ok_or_else(Api::no_rates)?;
+        let converted = match req.granularity.unwrap_or_default() {
+            Granularity::Daily => converted,
+            granularity => converted
+                .chunk_by(|a, b| granularity.period(a.date) == granularity.period(b.date))
+                .filter_map(Day::average)
+                .map(Cow::Owned)
+                .collect(),
+        };
 
-        let conversion = match req
-            .conversion
-            .as_ref()
-            .map(|c| Conversion::from_params(&c, &dataset))
-        {
-            // Supplied → use it
-            Some(Ok(conversion)) => conversion,
-            // Error → return it
-            Some(Err(e)) => return Ok(e.into()),
-            // None → use default
-            None => Conversion::default(),
+        let (converted, anchors) = match req.rebase {
+            Some(base) => {
+                let (converted, anchors) = rebase(converted, base);
+
+                let anchors = dataset
+                    .currencies
+                    .iter()
+                    .zip(anchors)
+                    .filter(|(c, _)| {
+                        conversion.to.is_empty() || conversion.to.iter().any(|to| **to == ***c)
+                    })
+                    .filter_map(|(c, anchor)| Some((c.to_string(), anchor?)))
+                    .collect::<HashMap<_, _>>();
+
+                (converted, Some(anchors))
+            }
+            None => (converted, None),
         };
 
-        let rates = days
+        let converted = req.mode.unwrap_or_default().apply(converted);
+
+        // Averaging can overflow, and rates that underflowed to 0 divide by zero
+        // in `rebase` and `mode`, neither of which JSON can represent
+        let finite = converted
+            .iter()
+            .all(|day| day.rates.iter().flatten().all(|rate| rate.is_finite()));
+        if !finite {
+            return Err(Api::rates_out_of_range());
+        }
+
+        let rates = converted
             .into_iter()
-            .filter_map(|day| {
-                day.clone()
-                    .convert(conversion.from, dataset.currencies)
-                    .map(|rates| {
-                        let mut rates = rates.to_hashmap(dataset.currencies);
-
-                        if !conversion.to.is_empty() {
-                            rates = rates
-                                .into_iter()
-                                .filter(|(c, _)| conversion.to.contains(c))
-                                .collect::<HashMap<_, _>>();
-                        };
-
-                        Rates {
-                            date: day.date,
-                            rates,
-                        }
-                    })
+            .map(|day| {
+                let mut rates = day.to_hashmap(&dataset.currencies);
+
+                if !conversion.to.is_empty() {
+                    rates = rates
+                        .into_iter()
+                        .filter(|(c, _)| conversion.to.contains(c))
+                        .collect::<HashMap<_, _>>();
+                };
+
+                if req.drop_nulls.unwrap_or(false) {
+                    rates.retain(|_, rate| rate.is_some());
+                }
+
+                if let Some(places) = req.places {
+                    for rate in rates.values_mut() {
+                        *rate = rate.map(|r| round(r, places));
+                    }
+                }
+
+                Rates {
+                    date: day.date,
+                    requested_date: None,
+                    days_offset: None,
+                    base: conversion.from.to_string(),
+                    rates: RateList::new(rates, &conversion.to, req.ordered.unwrap_or(false)),
+                }
             })
             .collect::<Vec<_>>();
 
-        Ok(RatesResponse::Ok(Json(Timeframe {
-            timeframe: [
-                rates.first().map(|d| d.date).unwrap(),
-                rates.last().map(|d| d.date).unwrap(),
-            ],
+        let timeframe = match (rates.first(), rates.last()) {
+            (Some(first), Some(last)) => [first.date, last.date],
+            // If there were days but none of them could be converted,
+            // the base currency did not exist yet in the timeframe
+            _ if !days.is_empty() => return Ok(conversion.unavailable_base().into()),
+            _ => return Err(Api::no_rates()),
+        };
+
+        let missing_dates = req
+            .missing_dates
+            .unwrap_or(false)
+            .then(|| Api::missing_dates(&dataset, req.timeframe, days));
+
+        let total = rates.len();
+        let rates = rates
+            .into_iter()
+            .skip(req.offset.unwrap_or(0))
+            .take(req.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        Ok(FormattedResponse::Ok(Formatted::new(
+            Timeframe {
+                timeframe,
+                total,
+                rates,
+                anchors,
+                missing_dates,
+            },
+            Format::negotiate(format.0, accept.as_deref()),
+        )))
+    }
+
+    /// Returns the rates of every trading day in the given calendar year
+    #[oai(path = "/rates/year/:year", method = "get", operation_id = "year")]
+    async fn year(
+        &self,
+        dataset: Data<&SharedDataset>,
+        year: Path<i32>,
+        #[oai(validator(pattern = "^\\s*([A-Za-z]{3})\\s*$"))] from: Query<Option<String>>,
+        to: Query<Option<Vec<String>>>,
+        format: Query<Option<Format>>,
+        #[oai(name = "Accept")] accept: Header<Option<String>>,
+    ) -> poem::Result<FormattedResponse<Timeframe>> {
+        let [first, last] = data::snapshot(&dataset)
+            .await
+            .timeframe()
+            .ok_or_else(Api::no_rates)?;
+
+        let (start, end) = match (
+            NaiveDate::from_ymd_opt(year.0, 1, 1),
+            NaiveDate::from_ymd_opt(year.0, 12, 31),
+        ) {
+            (Some(start), Some(end)) if start <= last && end >= first => (start, end),
+            _ => return Err(Api::not_in_dataset(year.0, [first, last])),
+        };
+
+        let req = TimeframeRequest {
+            timeframe: [Some(start), Some(end)],
+            conversion: Some(ConversionParams {
+                from: from.0,
+                to: to.0,
+            }),
+            ..Default::default()
+        };
+
+        self.timeframe(dataset, Json(req), format, accept).await
+    }
+
+    /// Returns the minimum, maximum, and average rates over the given timeframe
+    #[oai(
+        path = "/rates/timeframe/stats",
+        method = "post",
+        operation_id = "timeframe_stats"
+    )]
+    async fn timeframe_stats(
+        &self,
+        dataset: Data<&SharedDataset>,
+        req: Json<StatsRequest>,
+    ) -> poem::Result<RatesResponse<TimeframeStats>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let range = Api::timeframe_range(&dataset, req.timeframe)?;
+        let days = dataset.days.get(range.clone()).ok_or_else(Api::no_rates)?;
+
+        let conversion = match Conversion::resolve(req.conversion.as_ref(), &dataset)? {
+            Ok(conversion) => conversion,
+            Err(e) => return Ok(e.into()),
+        };
+
+        // Convert every day just once instead of once per currency
+        let days = dataset
+            .convert_days(days, &conversion.from)
+            .collect::<Vec<_>>();
+
+        let timeframe = match (days.first(), days.last()) {
+            (Some(first), Some(last)) => [first.date, last.date],
+            // The range has days, so none of them could be converted,
+            // the base currency did not exist yet in the timeframe
+            _ => return Ok(conversion.unavailable_base().into()),
+        };
+
+        let stats = dataset
+            .currencies
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                conversion.to.is_empty() || conversion.to.iter().any(|to| **to == ***c)
+            })
+            .map(|(index, c)| {
+                let stats = if &*conversion.from == data::EUR {
+                    // EUR-based rates are already stored per currency
+                    CurrencyStats::from_series(dataset.series(c, range.clone()))
+                } else {
+                    let series = days.iter().map(|day| (day.date, day.rates[index]));
+                    CurrencyStats::from_series(series)
+                };
+
+                (c.to_string(), stats)
+            })
+            .collect::<HashMap<_, _>>();
+
+        Ok(RatesResponse::Ok(Json(TimeframeStats {
+            timeframe,
+            base: conversion.from.to_string(),
+            stats,
+        })))
+    }
+
+    /// Returns how the rates changed between two dates
+    #[oai(path = "/fluctuation", method = "post", operation_id = "fluctuation")]
+    async fn fluctuation(
+        &self,
+        dataset: Data<&SharedDataset>,
+        req: Json<FluctuationRequest>,
+    ) -> poem::Result<RatesResponse<Fluctuations>> {
+        let dataset = data::snapshot(&dataset).await;
+
+        let conversion = match Conversion::resolve(req.conversion.as_ref(), &dataset)? {
+            Ok(conversion) => conversion,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let start = Api::day_for(&dataset, Some(req.start_date))?;
+        let end = Api::day_for(&dataset, Some(req.end_date))?;
+
+        // The base currency has to exist on both days
+        let (start_date, end_date) = (start.date, end.date);
+        let (start, end) = match (
+            start.convert(&conversion.from, &dataset),
+            end.convert(&conversion.from, &dataset),
+        ) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(conversion.unavailable_base().into()),
+        };
+
+        let rates = dataset
+            .currencies
+            .iter()
+            .zip(start.rates.iter().zip(end.rates.iter()))
+            .filter(|(c, _)| {
+                conversion.to.is_empty() || conversion.to.iter().any(|to| **to == ***c)
+            })
+            .map(|(c, (&start_rate, &end_rate))| {
+                // Currencies missing on either day have no change
+                let change = start_rate.zip(end_rate).map(|(start, end)| end - start);
+                let change_pct = start_rate
+                    .zip(change)
+                    .map(|(start, change)| change / start * 100.0);
+
+                let fluctuation = Fluctuation {
+                    start_rate,
+                    end_rate,
+                    change,
+                    change_pct,
+                };
+
+                (c.to_string(), fluctuation)
+            })
+            .collect::<HashMap<_, _>>();
+
+        Ok(RatesResponse::Ok(Json(Fluctuations {
+            start_date,
+            end_date,
+            base: conversion.from.to_string(),
             rates,
         })))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use poem::{http::Method, Endpoint, EndpointExt, Request};
+    use poem_openapi::OpenApiService;
+    use tokio::{
+        sync::{broadcast, RwLock},
+        time::timeout,
+    };
+
+    use super::*;
+
+    fn dataset(days: &[(u32, f64)]) -> Arc<Dataset> {
+        let days = days
+            .iter()
+            .map(|(day, usd)| Day {
+                date: NaiveDate::from_ymd_opt(2024, 1, *day).unwrap(),
+                rates: vec![Some(1.0), Some(*usd)],
+            })
+            .collect();
+
+        Arc::new(Dataset::new(
+            days,
+            data::shared_currencies(vec!["EUR".into(), "USD".into()]),
+        ))
+    }
+
+    async fn next_event(events: &mut BoxStream<'_, String>) -> String {
+        timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("an event in time")
+            .expect("an open stream")
+    }
+
+    #[tokio::test]
+    async fn stream_sends_the_latest_rates_on_connect_and_on_updates() {
+        let shared: SharedDataset = Arc::new(RwLock::new(dataset(&[(2, 1.1)])));
+        let (updates, _) = broadcast::channel(1);
+
+        let mut events = Api
+            .stream(Data(&shared), Data(&updates))
+            .await
+            .into_response()
+            .into_body()
+            .into_bytes_stream()
+            .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+            .boxed();
+
+        let event = next_event(&mut events).await;
+        assert!(event.starts_with("data: "), "{}", event);
+        assert!(event.contains(r#""date":"2024-01-02""#), "{}", event);
+
+        // What the scheduler does after downloading a new dataset
+        *shared.write().await = dataset(&[(2, 1.1), (3, 1.2)]);
+        updates.send(()).unwrap();
+
+        let event = next_event(&mut events).await;
+        assert!(event.contains(r#""date":"2024-01-03""#), "{}", event);
+        assert!(event.contains(r#""USD":1.2"#), "{}", event);
+
+        // A disconnecting client stops listening for updates
+        drop(events);
+        assert_eq!(updates.receiver_count(), 0);
+    }
+
+    /// Posts the request to `/rates/timeframe` over two days in the same month,
+    /// with USD below 1 so that the smallest amount underflows it to 0
+    async fn post_timeframe(body: &str) -> StatusCode {
+        let shared: SharedDataset = Arc::new(RwLock::new(dataset(&[(2, 0.4), (3, 0.5)])));
+        let service = OpenApiService::new(Api, "Exchange rates API", "1.0").data(shared);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri_str("/rates/timeframe")
+            .content_type("application/json")
+            .body(body.to_string());
+
+        service.get_response(request).await.status()
+    }
+
+    #[tokio::test]
+    async fn timeframe_rejects_a_zero_amount() {
+        let returns = r#"{"timeframe":[null,null],"amount":0,"mode":"returns"}"#;
+        let rebase = r#"{"timeframe":[null,null],"amount":0,"rebase":100}"#;
+
+        assert_eq!(post_timeframe(returns).await, StatusCode::BAD_REQUEST);
+        assert_eq!(post_timeframe(rebase).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn timeframe_rejects_rates_that_underflow() {
+        let rebase = r#"{"timeframe":[null,null],"amount":5e-324,"rebase":100}"#;
+        let returns = r#"{"timeframe":[null,null],"amount":5e-324,"mode":"returns"}"#;
+
+        assert_eq!(post_timeframe(rebase).await, StatusCode::BAD_REQUEST);
+        assert_eq!(post_timeframe(returns).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn timeframe_rejects_averages_that_overflow() {
+        let monthly = r#"{"timeframe":[null,null],"amount":1e308,"granularity":"monthly"}"#;
+
+        assert_eq!(post_timeframe(monthly).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            post_timeframe(r#"{"timeframe":[null,null],"amount":100}"#).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn timeframe_rejects_a_huge_rebase() {
+        let huge = r#"{"timeframe":[null,null],"rebase":1e307}"#;
+
+        assert_eq!(post_timeframe(huge).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            post_timeframe(r#"{"timeframe":[null,null],"rebase":1000000}"#).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_without_base_rates_is_currencies_not_found() {
+        let days = [(2, None), (3, None)].map(|(day, usd)| Day {
+            date: NaiveDate::from_ymd_opt(2024, 1, day).unwrap(),
+            rates: vec![Some(1.0), usd],
+        });
+        let dataset = Dataset::new(
+            days.to_vec(),
+            data::shared_currencies(vec!["EUR".into(), "USD".into()]),
+        );
+        let shared: SharedDataset = Arc::new(RwLock::new(Arc::new(dataset)));
+
+        let req = StatsRequest {
+            timeframe: [None, None],
+            conversion: Some(ConversionParams {
+                from: Some("USD".to_string()),
+                to: None,
+            }),
+        };
+        let response = Api.timeframe_stats(Data(&shared), Json(req)).await;
+
+        assert!(matches!(response, Ok(RatesResponse::NotFound(_))));
+    }
+
+    #[test]
+    fn missing_dates_cover_the_resolved_window() {
+        let dataset = dataset(&[(2, 1.1), (5, 1.2), (9, 1.3)]);
+        let date = |day| NaiveDate::from_ymd_opt(2024, 1, day);
+
+        let timeframe = [date(1), date(7)];
+        let Ok(days) = Api::timeframe_days(&dataset, timeframe) else {
+            panic!("the window has days");
+        };
+        let dates = days.iter().map(|day| day.date).collect::<Vec<_>>();
+
+        assert_eq!(dates, [date(2), date(5)].map(Option::unwrap));
+        // Nothing before the dataset, but the weekend at the end of the window
+        assert_eq!(
+            Api::missing_dates(&dataset, timeframe, days),
+            [3, 4, 6, 7].map(|day| date(day).unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn date_before_the_dataset_is_json() {
+        let dataset = dataset(&[(2, 1.1), (3, 1.2)]);
+        let date = NaiveDate::from_ymd_opt(2023, 12, 1);
+
+        let Err(e) = Api::day_for(&dataset, date) else {
+            panic!("the date is before the dataset");
+        };
+        let response = poem::Error::from(e).into_response();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.into_body().into_string().await.unwrap(),
+            r#"{"date":"2023-12-01","timeframe":["2024-01-02","2024-01-03"]}"#
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    env,
+    sync::{Arc, LazyLock},
+};
+
+use poem::{http::header, Endpoint, IntoResponse, Request, Response};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+
+/// When set, the API endpoints require it as a bearer token
+static API_TOKEN: LazyLock<Option<String>> =
+    LazyLock::new(|| env::var("API_TOKEN").ok().filter(|token| !token.is_empty()));
+
+/// Whether the protected endpoints need the `API_TOKEN`
+pub fn requires_token() -> bool {
+    API_TOKEN.is_some()
+}
+
+/// Extracts the token from an `Authorization: Bearer <token>` header
+fn bearer_token(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Compares a supplied token in constant time, so the response time does
+/// not reveal how much of it matched. Hashing first evens out the lengths
+pub fn tokens_match(supplied: &str, token: &str) -> bool {
+    let (supplied, token) = (Sha256::digest(supplied), Sha256::digest(token));
+
+    supplied
+        .iter()
+        .zip(token.iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+/// Health checks have to work without a token, and the admin endpoints
+/// check their own `ADMIN_TOKEN` in the same header
+fn is_protected(path: &str) -> bool {
+    path != "/health" && !path.starts_with("/admin/")
+}
+
+/// Rejects API requests without the `API_TOKEN`, everything stays open without it.
+/// The docs, the OpenAPI spec, and `/metrics` are outside the API
+pub async fn require_api_token<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    if let Some(token) = API_TOKEN.as_deref() {
+        let is_protected = is_protected(req.uri().path());
+
+        let authorized = bearer_token(&req).is_some_and(|supplied| tokens_match(supplied, token));
+
+        if is_protected && !authorized {
+            return Ok(StatusCode::UNAUTHORIZED
+                .with_header(header::WWW_AUTHENTICATE, "Bearer")
+                .into_response());
+        }
+    }
+
+    ep.call(req).await.map(IntoResponse::into_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_match_only_the_same_token() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret ", "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, Utc, Weekday};
+use poem::{
+    http::{header, HeaderMap, Method, StatusCode},
+    Endpoint, IntoResponse, Request, Response,
+};
+
+use crate::{
+    auth,
+    data::{self, Dataset, SharedDataset},
+};
+
+/// Stale data should be replaced soon, so it is not cached for long
+const STALE_MAX_AGE: i64 = 5 * 60;
+
+/// Only these change with the dataset, the stream never ends anyway
+fn is_cacheable(path: &str) -> bool {
+    path == "/"
+        || path.starts_with("/compat")
+        || (path.starts_with("/rate") && path != "/rates/stream")
+}
+
+/// Identifies the dataset and the format the response was built from,
+/// and how long it stays fresh
+struct Validators {
+    etag: String,
+    last_modified: DateTime<Utc>,
+    max_age: i64,
+}
+
+/// Responses stay fresh until the next scheduled update. When the last update
+/// should have brought new rates but did not, they are considered stale
+fn max_age(dataset: &Dataset) -> i64 {
+    let now = data::now();
+    let until_update = (data::next_scheduled_update() - now).num_seconds().max(0);
+
+    // The ECB does not publish on weekends
+    let last_update = data::last_scheduled_update();
+    let expected = last_update.date_naive();
+    let is_stale = !matches!(expected.weekday(), Weekday::Sat | Weekday::Sun)
+        && dataset.days.last().is_none_or(|day| day.date < expected);
+
+    match is_stale {
+        true => until_update.min(STALE_MAX_AGE),
+        false => until_update,
+    }
+}
+
+impl Validators {
+    /// The same URL can be JSON, CSV, or XML depending on `Accept`, so the
+    /// content type is part of the ETag
+    fn of(dataset: &Dataset, resp: &Response) -> Self {
+        let last_date = dataset.days.last().map(|day| day.date).unwrap_or_default();
+        let format = resp
+            .content_type()
+            .and_then(|content_type| content_type.split(';').next())
+            .and_then(|mime| mime.split('/').nth(1))
+            .unwrap_or_default()
+            .trim();
+
+        Self {
+            etag: format!(
+                "\"{}-{}-{}\"",
+                last_date,
+                dataset.loaded_at.timestamp(),
+                format
+            ),
+            last_modified: dataset.loaded_at,
+            max_age: max_age(dataset),
+        }
+    }
+
+    /// Whether the client already has this version. `If-None-Match` takes
+    /// precedence, `If-Modified-Since` is only used without it
+    fn is_fresh(&self, headers: &HeaderMap) -> bool {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+        if let Some(if_none_match) = header(header::IF_NONE_MATCH) {
+            return if_none_match
+                .split(',')
+                .map(|etag| etag.trim().trim_start_matches("W/"))
+                .any(|etag| etag == "*" || etag == self.etag);
+        }
+
+        header(header::IF_MODIFIED_SINCE)
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .is_some_and(|since| self.last_modified <= since)
+    }
+
+    fn apply(&self, mut resp: Response) -> Response {
+        resp.headers_mut().insert(
+            header::ETAG,
+            self.etag.parse().expect("ETags are valid header values"),
+        );
+        resp.headers_mut().insert(
+            header::LAST_MODIFIED,
+            self.last_modified
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string()
+                .parse()
+                .expect("HTTP dates are valid header values"),
+        );
+        // Shared caches must not hand out responses that needed the token
+        let visibility = match auth::requires_token() {
+            true => "private",
+            false => "public",
+        };
+        resp.headers_mut().insert(
+            header::CACHE_CONTROL,
+            format!("{}, max-age={}", visibility, self.max_age)
+                .parse()
+                .expect("Cache-Control is a valid header value"),
+        );
+        // The format is negotiated and the body might be compressed
+        resp.headers_mut().insert(
+            header::VARY,
+            "Accept, Accept-Encoding"
+                .parse()
+                .expect("Vary is a valid header value"),
+        );
+
+        resp
+    }
+}
+
+/// Adds `ETag`, `Last-Modified`, `Cache-Control`, and `Vary` to GET responses
+/// built from the dataset and answers with 304 Not Modified when the client is
+/// up to date. The request is handled either way, so that errors are never 304
+pub async fn cache_headers<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    let dataset = match req.data::<SharedDataset>() {
+        Some(dataset) if req.method() == Method::GET && is_cacheable(req.uri().path()) => {
+            data::snapshot(dataset).await
+        }
+        _ => return ep.call(req).await.map(IntoResponse::into_response),
+    };
+
+    let headers = req.headers().clone();
+    let resp = ep.call(req).await?.into_response();
+
+    if !resp.status().is_success() {
+        return Ok(resp);
+    }
+
+    let validators = Validators::of(&dataset, &resp);
+
+    Ok(match validators.is_fresh(&headers) {
+        true => validators.apply(StatusCode::NOT_MODIFIED.into_response()),
+        false => validators.apply(resp),
+    })
+}
@@ -1,34 +1,237 @@
 use std::{
+    borrow::{Borrow, Cow},
     collections::{HashMap, HashSet},
     env,
-    sync::Arc,
-    time::Duration,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
 };
 
-use chrono::{NaiveDate, Timelike};
-use chrono_tz::Europe::Berlin;
-use serde::Deserialize;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Timelike, Utc, Weekday};
+use chrono_tz::{Europe::Berlin, Tz};
+use futures::future::BoxFuture;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::{
-    fs::{File, OpenOptions},
+    fs::File,
     io::{AsyncReadExt, AsyncWriteExt},
-    sync::RwLock,
+    sync::{broadcast, RwLock},
 };
 
+use crate::metrics;
+
+/// The ECB asks for the source to be acknowledged when its rates are redistributed
+pub const ATTRIBUTION: &str =
+    "Euro foreign exchange reference rates by the European Central Bank (ECB), \
+    https://www.ecb.europa.eu/stats/policy_and_exchange_rates/euro_reference_exchange_rates/";
+
+/// Defaults for `DATA_DIRECTORY` and `DATA_FILE`, which locate the cached dataset
 const DATA_DIRECTORY: &str = "data";
+const DATA_FILE: &str = "dataset.xml";
+
+/// Bump whenever the snapshot format or `Day` changes, older snapshots are then ignored
+const SNAPSHOT_VERSION: u32 = 1;
+
+// ECB publishes the same `Cube` structure in three flavours, selected by `DATASET_MODE`:
+// - `hist` (default): every day since 1999
+// - `90d`: the last 90 days
+// - `daily`: only the latest day
 const DATASET_HIST_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-hist.xml";
+const DATASET_90D_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-hist-90d.xml";
+const DATASET_DAILY_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml";
+
+/// Lets the ECB know who to contact about high-volume clients
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/michaljanocko/exchangerates)"
+);
+
+/// Shared by every download, so that connections are pooled and every request
+/// identifies this service. Times out after `DOWNLOAD_TIMEOUT` seconds (30 by default)
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    let timeout = env::var("DOWNLOAD_TIMEOUT")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(30));
 
-pub type SharedDataset = Arc<RwLock<Dataset>>;
+    reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(timeout)
+        .timeout(timeout)
+        .build()
+        .expect("Failed to build the HTTP client")
+});
 
-pub type Currency = &'static str;
-pub const EUR: Currency = "EUR";
+// ECB rates are usually updated at 16:00 CET, but we use 18:00 CET,
+// just to be sure we actually get the newest rates
+const DEFAULT_UPDATE_AT: u32 = 18 * 60;
+
+/// Updates never start closer together than this, whatever the schedule says
+const DEFAULT_MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The lock only guards swapping in a new dataset. Readers take a [`snapshot`]
+/// and release it right away, so that updates never wait for slow requests
+pub type SharedDataset = Arc<RwLock<Arc<Dataset>>>;
+
+/// Returns the current dataset, which stays usable after an update swaps it out
+pub async fn snapshot(dataset: &SharedDataset) -> Arc<Dataset> {
+    Arc::clone(&*dataset.read().await)
+}
+
+/// Notifies subscribers every time the scheduler swaps in a new dataset
+pub type DatasetUpdates = broadcast::Sender<()>;
+
+/// The outcome of the latest update, `None` until the first one after startup
+pub type SharedLastUpdate = Arc<RwLock<Option<LastUpdate>>>;
+
+#[derive(Clone)]
+pub struct LastUpdate {
+    pub at: DateTime<Utc>,
+    /// Dates the current dataset did not have before
+    pub added: Vec<NaiveDate>,
+    /// Why the update failed, the dataset stayed the same then
+    pub error: Option<String>,
+}
+
+/// Shared by every day and response of a dataset, and dropped along with it
+pub type Currency = Arc<str>;
+pub const EUR: &str = "EUR";
+
+/// Names and symbols of every currency the ECB has published, sorted by code
+const CURRENCY_INFO: &[(&str, &str, &str)] = &[
+    ("AUD", "Australian Dollar", "A$"),
+    ("BGN", "Bulgarian Lev", "лв"),
+    ("BRL", "Brazilian Real", "R$"),
+    ("CAD", "Canadian Dollar", "C$"),
+    ("CHF", "Swiss Franc", "CHF"),
+    ("CNY", "Chinese Yuan Renminbi", "¥"),
+    ("CYP", "Cypriot Pound", "£"),
+    ("CZK", "Czech Koruna", "Kč"),
+    ("DKK", "Danish Krone", "kr"),
+    ("EEK", "Estonian Kroon", "kr"),
+    ("EUR", "Euro", "€"),
+    ("GBP", "Pound Sterling", "£"),
+    ("HKD", "Hong Kong Dollar", "HK$"),
+    ("HRK", "Croatian Kuna", "kn"),
+    ("HUF", "Hungarian Forint", "Ft"),
+    ("IDR", "Indonesian Rupiah", "Rp"),
+    ("ILS", "Israeli Shekel", "₪"),
+    ("INR", "Indian Rupee", "₹"),
+    ("ISK", "Icelandic Krona", "kr"),
+    ("JPY", "Japanese Yen", "¥"),
+    ("KRW", "South Korean Won", "₩"),
+    ("LTL", "Lithuanian Litas", "Lt"),
+    ("LVL", "Latvian Lats", "Ls"),
+    ("MTL", "Maltese Lira", "Lm"),
+    ("MXN", "Mexican Peso", "$"),
+    ("MYR", "Malaysian Ringgit", "RM"),
+    ("NOK", "Norwegian Krone", "kr"),
+    ("NZD", "New Zealand Dollar", "NZ$"),
+    ("PHP", "Philippine Peso", "₱"),
+    ("PLN", "Polish Zloty", "zł"),
+    ("ROL", "Romanian Leu (old)", "lei"),
+    ("RON", "Romanian Leu", "lei"),
+    ("RUB", "Russian Rouble", "₽"),
+    ("SEK", "Swedish Krona", "kr"),
+    ("SGD", "Singapore Dollar", "S$"),
+    ("SIT", "Slovenian Tolar", "SIT"),
+    ("SKK", "Slovak Koruna", "Sk"),
+    ("THB", "Thai Baht", "฿"),
+    ("TRL", "Turkish Lira (old)", "TL"),
+    ("TRY", "Turkish Lira", "₺"),
+    ("USD", "US Dollar", "$"),
+    ("ZAR", "South African Rand", "R"),
+];
+
+/// Looks up the human-readable name and symbol of a currency
+pub fn currency_info(code: &str) -> Option<(&'static str, &'static str)> {
+    let index = CURRENCY_INFO
+        .binary_search_by_key(&code, |(code, _, _)| code)
+        .ok()?;
+    let (_, name, symbol) = CURRENCY_INFO[index];
+
+    Some((name, symbol))
+}
+
+/// How to pick a day when there are no rates for the requested date
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Use the closest day before the date
+    Previous,
+    /// Use the closest day after the date
+    Next,
+    /// Only use the date itself
+    Exact,
+}
 
 #[derive(Clone)]
 pub struct Dataset {
     pub days: Vec<Day>,
-    pub currencies: &'static [Currency],
+    pub currencies: Arc<[Currency]>,
+
+    /// Index of every currency in `currencies`, so we do not have to search for them
+    indices: HashMap<Currency, usize>,
+    /// The same rates as in `days`, but stored per currency,
+    /// so that time series do not have to go through every day
+    columns: Vec<Vec<Option<f64>>>,
+    /// When the dataset was loaded, changes with every update
+    pub loaded_at: DateTime<Utc>,
 }
 
 impl Dataset {
+    pub fn new(days: Vec<Day>, currencies: Arc<[Currency]>) -> Self {
+        let indices = currencies
+            .iter()
+            .enumerate()
+            .map(|(index, currency)| (Currency::clone(currency), index))
+            .collect();
+
+        let columns = (0..currencies.len())
+            .map(|index| days.iter().map(|day| day.rates[index]).collect())
+            .collect();
+
+        Self {
+            days,
+            currencies,
+            indices,
+            columns,
+            // HTTP dates only have a precision of seconds
+            loaded_at: Utc::now().with_nanosecond(0).unwrap_or_else(Utc::now),
+        }
+    }
+
+    /// Returns the EUR-based rates of a single currency for the days in the
+    /// range, without going through the others. Unknown currencies, and ranges
+    /// outside the days, have an empty series
+    pub fn series(
+        &self,
+        code: &str,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (NaiveDate, Option<f64>)> + '_ {
+        let days = self.days.get(range.clone()).unwrap_or_default();
+        let column = self
+            .index_of(code)
+            .and_then(|index| self.columns[index].get(range))
+            .unwrap_or_default();
+
+        days.iter().map(|day| day.date).zip(column.iter().copied())
+    }
+
+    /// Returns the index of the currency in `currencies` and in every day's rates
+    pub fn index_of(&self, code: &str) -> Option<usize> {
+        self.indices.get(code).copied()
+    }
+
     pub fn timeframe(&self) -> Option<[NaiveDate; 2]> {
         let first = self.days.first()?;
         let last = self.days.last()?;
@@ -36,15 +239,183 @@ impl Dataset {
         Some([first.date, last.date])
     }
 
-    /// Convert a currency code to a static one from the dataset
-    pub fn from(&self, from: &String) -> Option<&'static str> {
-        let index = self.currencies.binary_search(&from.as_str()).ok()?;
+    /// Adds the rates of a day, or updates them when the day is already there.
+    /// Currencies the dataset does not have yet are added to every day, with
+    /// no rates before this one
+    pub fn insert_day(&mut self, date: NaiveDate, rates: &[(&str, f64)]) {
+        let mut currencies = self.currencies.to_vec();
+        for (code, _) in rates.iter() {
+            if !currencies.iter().any(|currency| &**currency == *code) {
+                currencies.push(Currency::from(*code));
+            }
+        }
+        currencies.sort();
+
+        let mut days = std::mem::take(&mut self.days);
+
+        // Every day's rates follow the order of `currencies`, so they have to be reindexed
+        if currencies.len() != self.currencies.len() {
+            for day in days.iter_mut() {
+                let mut reindexed = vec![None; currencies.len()];
+                for (code, rate) in self.currencies.iter().zip(day.rates.iter()) {
+                    // Unwrapping is safe because the new currencies include the old ones
+                    reindexed[currencies.binary_search(code).unwrap()] = *rate;
+                }
+                day.rates = reindexed;
+            }
+        }
+
+        let index = match days.binary_search_by_key(&date, |day| day.date) {
+            Ok(index) => index,
+            Err(index) => {
+                days.insert(
+                    index,
+                    Day {
+                        date,
+                        rates: vec![None; currencies.len()],
+                    },
+                );
+                index
+            }
+        };
+
+        for (code, rate) in rates.iter() {
+            if let Ok(currency) = currencies.binary_search_by(|c| (**c).cmp(*code)) {
+                days[index].rates[currency] = Some(*rate);
+            }
+        }
+
+        // Rebuilds the indices and columns
+        *self = Dataset::new(days, currencies.into());
+    }
+
+    /// Merges datasets into one with every date and currency of them.
+    /// When more of them have the same rate, the earlier one wins
+    pub fn merge(datasets: &[Dataset]) -> Dataset {
+        let mut currencies = datasets
+            .iter()
+            .flat_map(|dataset| dataset.currencies.iter().cloned())
+            .collect::<Vec<_>>();
+        currencies.sort();
+        currencies.dedup();
+        let currencies: Arc<[Currency]> = currencies.into();
+
+        let mut dates = datasets
+            .iter()
+            .flat_map(|dataset| dataset.days.iter().map(|day| day.date))
+            .collect::<Vec<_>>();
+        dates.sort();
+        dates.dedup();
+
+        let days = dates
+            .into_iter()
+            .map(|date| {
+                let mut rates = vec![None; currencies.len()];
+
+                // Going from the last one, so that earlier datasets overwrite the later ones
+                for dataset in datasets.iter().rev() {
+                    let Some(index) = dataset.index_for_date(date, Rounding::Exact) else {
+                        continue;
+                    };
+
+                    for (currency, rate) in
+                        dataset.currencies.iter().zip(&dataset.days[index].rates)
+                    {
+                        if let (Some(rate), Ok(index)) = (rate, currencies.binary_search(currency))
+                        {
+                            rates[index] = Some(*rate);
+                        }
+                    }
+                }
+
+                Day { date, rates }
+            })
+            .collect();
+
+        Dataset::new(days, currencies)
+    }
+
+    /// Whether `self` covers less than half the days or the timespan of `other`,
+    /// which usually means the ECB served a truncated feed
+    pub fn is_much_smaller_than(&self, other: &Dataset) -> bool {
+        let span = |dataset: &Dataset| {
+            dataset
+                .timeframe()
+                .map_or(0, |[first, last]| (last - first).num_days())
+        };
+
+        self.days.len() * 2 < other.days.len() || span(self) * 2 < span(other)
+    }
+
+    /// Finds the index of the day on `date`. When the ECB did not publish
+    /// rates that day (weekends and holidays), `rounding` decides which day to use
+    pub fn index_for_date(&self, date: NaiveDate, rounding: Rounding) -> Option<usize> {
+        match (
+            self.days.binary_search_by_key(&date, |day| day.date),
+            rounding,
+        ) {
+            (Ok(index), _) => Some(index),
+            (Err(_), Rounding::Exact) => None,
+            (Err(index), Rounding::Previous) => index.checked_sub(1),
+            (Err(index), Rounding::Next) => (index < self.days.len()).then_some(index),
+        }
+    }
+
+    /// Resolves a timeframe to a range of day indices. Both ends are inclusive
+    /// and snap inwards, so a start on a weekend begins on the next Monday and
+    /// an end on a weekend stops on the previous Friday. Missing ends extend to
+    /// the first or last day. When no day falls inside the timeframe, the range
+    /// is empty
+    pub fn timeframe_range(
+        &self,
+        start: Option<NaiveDate>,
+        end: Option<NaiveDate>,
+    ) -> Range<usize> {
+        let start_index =
+            start.map_or(0, |start| self.days.partition_point(|day| day.date < start));
+        let inside = self
+            .days
+            .get(start_index)
+            .is_some_and(|day| end.is_none_or(|end| day.date <= end));
+
+        if !inside {
+            return start_index..start_index;
+        }
+
+        let end_index = end
+            .and_then(|end| self.index_for_date(end, Rounding::Previous))
+            // The range is exclusive, so we need to add one
+            .map_or(self.days.len(), |index| index + 1);
+
+        start_index..end_index
+    }
+
+    /// Converts the days to the given base currency, skipping the days
+    /// when it was not available. The base currency is looked up just once
+    pub fn convert_days<'a>(
+        &self,
+        days: &'a [Day],
+        from: &str,
+    ) -> impl Iterator<Item = Cow<'a, Day>> + 'a {
+        // `None` means we do not need to convert at all
+        let from = (from != EUR).then(|| self.index_of(from));
 
-        Some(self.currencies[index])
+        days.iter().filter_map(move |day| match from {
+            None => Some(Cow::Borrowed(day)),
+            Some(Some(from)) => day.convert_by_index(from).map(Cow::Owned),
+            Some(None) => None,
+        })
+    }
+
+    /// Convert a currency code to the shared one from the dataset
+    pub fn from(&self, from: &str) -> Option<Currency> {
+        let index = self.index_of(from)?;
+
+        Some(Currency::clone(&self.currencies[index]))
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Day {
     pub date: NaiveDate,
 
@@ -54,226 +425,1465 @@ pub struct Day {
 }
 
 impl Day {
-    pub fn convert(self, from: &'static str, currencies: &'static [Currency]) -> Option<Self> {
+    /// Converts the rates to the given base currency. The day is only borrowed
+    /// for EUR, otherwise a new day with the converted rates is returned
+    pub fn convert(&self, from: &str, dataset: &Dataset) -> Option<Cow<'_, Self>> {
         // We do not need to convert if the base currency is EUR
         // ECB publishes the rates with Euro as the base currency
         if from == EUR {
-            return Some(self);
+            return Some(Cow::Borrowed(self));
         }
 
         // Find the index of the base currency
-        let from = currencies.binary_search(&from).ok()?;
-        // Get the base currency rate
-        let from_rate = self.rates.get(from)?.as_ref()?.clone();
-        // Convert all the rates
+        let from = dataset.index_of(from)?;
+
+        self.convert_by_index(from).map(Cow::Owned)
+    }
+
+    /// Same as `convert` but takes the index of the base currency, so it
+    /// does not have to be looked up again when converting many days
+    pub fn convert_by_index(&self, from: usize) -> Option<Self> {
+        // Get the base currency rate, which `parse_rate` made sure is positive
+        let from_rate = (*self.rates.get(from)?)?;
+
+        // Both rates are per EUR, so a cross rate such as USD → JPY is
+        // a single division of JPY/EUR by USD/EUR without going through EUR
         let rates = self
             .rates
-            .into_iter()
+            .iter()
             .map(|rate| rate.map(|r| r / from_rate))
             .collect::<Vec<_>>();
 
-        Some(Self { rates, ..self })
+        Some(Self {
+            date: self.date,
+            rates,
+        })
+    }
+
+    /// Same as `convert` but using decimal arithmetic, so the converted
+    /// rates do not pick up binary floating point errors
+    pub fn convert_decimal(&self, from: &str, dataset: &Dataset) -> Option<Vec<Option<Decimal>>> {
+        let rates = self
+            .rates
+            .iter()
+            .map(|rate| rate.and_then(to_decimal))
+            .collect::<Vec<_>>();
+
+        if from == EUR {
+            return Some(rates);
+        }
+
+        let from = dataset.index_of(from)?;
+        let from_rate = (*rates.get(from)?)?;
+
+        Some(
+            rates
+                .into_iter()
+                .map(|rate| rate.and_then(|r| r.checked_div(from_rate)))
+                .collect(),
+        )
+    }
+
+    /// Averages the rates over the given days, skipping the days when
+    /// a currency was not available. The average takes the date of the last day
+    pub fn average<D: Borrow<Day>>(days: &[D]) -> Option<Day> {
+        let last = days.last()?.borrow();
+
+        let rates = (0..last.rates.len())
+            .map(|index| {
+                let (sum, count) = days
+                    .iter()
+                    .filter_map(|day| day.borrow().rates[index])
+                    .fold((0.0, 0), |(sum, count), rate| (sum + rate, count + 1));
+
+                (count > 0).then(|| sum / count as f64)
+            })
+            .collect();
+
+        Some(Day {
+            date: last.date,
+            rates,
+        })
+    }
+
+    /// Returns the rate of a single currency if it was available that day
+    pub fn rate(&self, currency: &str, dataset: &Dataset) -> Option<f64> {
+        let index = dataset.index_of(currency)?;
+
+        *self.rates.get(index)?
     }
 
     /// Turns the day rates into a `HashMap` with currency codes as keys
-    pub fn to_hashmap(&self, currencies: &'static [Currency]) -> HashMap<String, Option<f64>> {
+    pub fn to_hashmap(&self, currencies: &[Currency]) -> HashMap<String, Option<f64>> {
         currencies
-            .into_iter()
+            .iter()
             .map(ToString::to_string)
-            .zip(self.rates.clone())
+            .zip(self.rates.iter().copied())
             .collect::<HashMap<_, _>>()
     }
 }
 
+/// Turns a float into a decimal using its shortest representation,
+/// which for the parsed rates is exactly what ECB published
+pub fn to_decimal(value: f64) -> Option<Decimal> {
+    Decimal::from_str(&value.to_string()).ok()
+}
+
+/// The current time in the ECB's timezone
+pub fn now() -> DateTime<Tz> {
+    chrono::Utc::now().with_timezone(&Berlin)
+}
+
+/// Today's date in the ECB's timezone
+pub fn today() -> NaiveDate {
+    now().date_naive()
+}
+
+fn cache_path() -> PathBuf {
+    let directory = env::var("DATA_DIRECTORY").unwrap_or_else(|_| DATA_DIRECTORY.to_string());
+    let file = env::var("DATA_FILE").unwrap_or_else(|_| DATA_FILE.to_string());
+
+    PathBuf::from(directory).join(file)
+}
+
 async fn cache_file() -> Option<File> {
-    OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(DATA_DIRECTORY.to_string() + "/dataset.xml")
+    let path = cache_path();
+
+    log::debug!("Using cache file {}", path.display());
+
+    File::open(&path)
         .await
+        .inspect_err(|e| log::info!("No cached dataset in {}: {}", path.display(), e))
         .ok()
 }
 
-pub async fn dataset() -> anyhow::Result<SharedDataset> {
-    let dataset = match cache_file().await {
+/// Writes into a temporary file that then replaces the cache, so that a shorter
+/// or interrupted write can never leave a corrupt cache behind
+async fn write_cache(path: &Path, data: &str) -> std::io::Result<()> {
+    if let Some(directory) = path.parent() {
+        tokio::fs::create_dir_all(directory).await?;
+    }
+
+    let temporary = path.with_extension("tmp");
+
+    let mut file = File::create(&temporary).await?;
+    file.write_all(data.as_bytes()).await?;
+    file.sync_all().await?;
+
+    tokio::fs::rename(&temporary, path).await?;
+
+    // Written last, so an interrupted write is caught by `verify_cache`
+    tokio::fs::write(checksum_path(path), checksum(data)).await?;
+
+    Ok(())
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".sha256");
+    path.into()
+}
+
+fn checksum(data: &str) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Makes sure the cache is exactly what was written by `write_cache`
+async fn verify_cache(path: &Path, data: &str) -> anyhow::Result<()> {
+    let expected = tokio::fs::read_to_string(checksum_path(path)).await?;
+
+    if expected.trim() != checksum(data) {
+        anyhow::bail!("checksum mismatch");
+    }
+
+    Ok(())
+}
+
+/// The parsed dataset next to the XML cache, which stays the source of truth
+fn snapshot_path() -> PathBuf {
+    cache_path().with_extension("bin")
+}
+
+/// The settings that change how the XML is parsed, datasets stored
+/// with different ones are not used
+pub fn parse_settings() -> String {
+    let allowed = allowed_currencies().map(|allowed| {
+        let mut allowed = allowed.into_iter().collect::<Vec<_>>();
+        allowed.sort();
+        allowed
+    });
+
+    format!("{:?} {:?}", allowed, pegged_currencies())
+}
+
+/// Stores the parsed dataset, so that restarts do not have to parse the XML
+async fn write_snapshot(dataset: &Dataset) -> anyhow::Result<PathBuf> {
+    let path = snapshot_path();
+    let data = bincode::serialize(&(
+        SNAPSHOT_VERSION,
+        parse_settings(),
+        dataset
+            .currencies
+            .iter()
+            .map(|currency| &**currency)
+            .collect::<Vec<_>>(),
+        &dataset.days,
+    ))?;
+
+    let temporary = path.with_extension("bin.tmp");
+    tokio::fs::write(&temporary, data).await?;
+    tokio::fs::rename(&temporary, &path).await?;
+
+    Ok(path)
+}
+
+/// Loads the snapshot when it was written after the XML cache
+/// with the same version and settings, `None` otherwise
+async fn read_snapshot() -> Option<Dataset> {
+    let path = snapshot_path();
+
+    let modified = |path| async move { tokio::fs::metadata(path).await?.modified() };
+    match (modified(path.clone()).await, modified(cache_path()).await) {
+        (Ok(snapshot), Ok(cache)) if snapshot >= cache => {}
+        _ => return None,
+    }
+
+    let data = tokio::fs::read(&path)
+        .await
+        .inspect_err(|e| log::warn!("Could not read snapshot {}: {}", path.display(), e))
+        .ok()?;
+
+    // Read the header first, older versions might not decode as the current one
+    let (version, settings) = bincode::deserialize::<(u32, String)>(&data).ok()?;
+    if version != SNAPSHOT_VERSION || settings != parse_settings() {
+        log::info!("Ignoring outdated snapshot {}", path.display());
+        return None;
+    }
+
+    let (_, _, currencies, days) =
+        bincode::deserialize::<(u32, String, Vec<String>, Vec<Day>)>(&data)
+            .inspect_err(|e| log::warn!("Could not decode snapshot {}: {}", path.display(), e))
+            .ok()?;
+
+    log::info!("Loaded dataset from snapshot {}", path.display());
+
+    Some(Dataset::new(days, shared_currencies(currencies)))
+}
+
+/// Reads, verifies, and parses the cached ECB dataset, `None` when there is no cache.
+/// A current snapshot skips all of that
+async fn read_cache() -> anyhow::Result<Option<Dataset>> {
+    let Some(mut file) = cache_file().await else {
+        return Ok(None);
+    };
+
+    if let Some(dataset) = read_snapshot().await {
+        return Ok(Some(dataset));
+    }
+
+    let mut data = String::new();
+    file.read_to_string(&mut data).await?;
+
+    verify_cache(&cache_path(), &data).await?;
+
+    let dataset = parse_dataset(data).await?;
+
+    if let Err(e) = write_snapshot(&dataset).await {
+        log::warn!("Could not write snapshot: {}", e);
+    }
+
+    Ok(Some(dataset))
+}
+
+/// A source of exchange rates. The ECB is the default one,
+/// others only need to build a `Dataset` of EUR-based rates
+pub trait RateProvider: Send + Sync {
+    /// Downloads the newest dataset
+    fn fetch(&self) -> BoxFuture<'_, anyhow::Result<Dataset>>;
+
+    /// Loads the dataset from the last `fetch`, for providers that keep one
+    fn cached(&self) -> BoxFuture<'_, anyhow::Result<Option<Dataset>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Where the rates come from, e.g. the URL of the feed
+    fn source(&self) -> String;
+
+    /// Brings the current dataset up to date, which is a whole new `fetch`
+    /// unless the provider can download just the newest days
+    fn fetch_update<'a>(&'a self, _current: &'a Dataset) -> BoxFuture<'a, anyhow::Result<Dataset>> {
+        self.fetch()
+    }
+}
+
+pub type SharedProvider = Arc<dyn RateProvider>;
+
+/// The ECB's XML feed, cached on disk
+pub struct Ecb;
+
+impl RateProvider for Ecb {
+    fn fetch(&self) -> BoxFuture<'_, anyhow::Result<Dataset>> {
+        Box::pin(download_ecb_dataset())
+    }
+
+    fn cached(&self) -> BoxFuture<'_, anyhow::Result<Option<Dataset>>> {
+        Box::pin(read_cache())
+    }
+
+    fn source(&self) -> String {
+        dataset_url()
+    }
+
+    fn fetch_update<'a>(&'a self, current: &'a Dataset) -> BoxFuture<'a, anyhow::Result<Dataset>> {
+        Box::pin(update_ecb_dataset(current))
+    }
+}
+
+/// A local file in the ECB's XML format, e.g. to fill in history the ECB lacks
+pub struct XmlFile(PathBuf);
+
+impl RateProvider for XmlFile {
+    fn fetch(&self) -> BoxFuture<'_, anyhow::Result<Dataset>> {
+        Box::pin(async {
+            let data = tokio::fs::read_to_string(&self.0).await?;
+            parse_dataset(data).await
+        })
+    }
+
+    fn source(&self) -> String {
+        self.0.display().to_string()
+    }
+
+    // Reading the file is cheap, so it is its own cache
+    fn cached(&self) -> BoxFuture<'_, anyhow::Result<Option<Dataset>>> {
+        Box::pin(async { self.fetch().await.map(Some) })
+    }
+}
+
+/// Combines several providers, the earlier ones win when they both have a rate
+pub struct Merged(Vec<SharedProvider>);
+
+impl Merged {
+    /// Merges what `fetch` gets from every provider. One failing provider
+    /// should not take the others down with it
+    async fn merge_each<'a>(
+        &'a self,
+        fetch: impl Fn(&'a SharedProvider) -> BoxFuture<'a, anyhow::Result<Dataset>>,
+    ) -> anyhow::Result<Dataset> {
+        let mut datasets = Vec::new();
+        let mut error = None;
+
+        for provider in self.0.iter() {
+            match fetch(provider).await {
+                Ok(dataset) => datasets.push(dataset),
+                Err(e) => {
+                    log::warn!("Provider failed, merging the others: {}", e);
+                    error = Some(e);
+                }
+            }
+        }
+
+        match error {
+            Some(e) if datasets.is_empty() => Err(e),
+            _ => Ok(Dataset::merge(&datasets)),
+        }
+    }
+}
+
+impl RateProvider for Merged {
+    fn fetch(&self) -> BoxFuture<'_, anyhow::Result<Dataset>> {
+        Box::pin(self.merge_each(|provider| provider.fetch()))
+    }
+
+    // Every provider updates the merged dataset, so the ECB can still append
+    // just the newest days
+    fn fetch_update<'a>(&'a self, current: &'a Dataset) -> BoxFuture<'a, anyhow::Result<Dataset>> {
+        Box::pin(self.merge_each(move |provider| provider.fetch_update(current)))
+    }
+
+    fn cached(&self) -> BoxFuture<'_, anyhow::Result<Option<Dataset>>> {
+        Box::pin(async {
+            let mut datasets = Vec::new();
+
+            // Without every cache, the merged dataset would be incomplete
+            for provider in self.0.iter() {
+                match provider.cached().await? {
+                    Some(dataset) => datasets.push(dataset),
+                    None => return Ok(None),
+                }
+            }
+
+            Ok(Some(Dataset::merge(&datasets)))
+        })
+    }
+
+    fn source(&self) -> String {
+        self.0
+            .iter()
+            .map(|provider| provider.source())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Builds the providers from `PROVIDERS`, a comma-separated list in order of priority.
+/// `ecb` stands for the ECB, anything else is a path to a file in the ECB's XML format
+pub fn provider() -> SharedProvider {
+    let Ok(providers) = env::var("PROVIDERS") else {
+        return Arc::new(Ecb);
+    };
+
+    let providers = providers
+        .split(',')
+        .map(str::trim)
+        .filter(|provider| !provider.is_empty())
+        .map(|provider| -> SharedProvider {
+            match provider {
+                "ecb" => Arc::new(Ecb),
+                path => Arc::new(XmlFile(PathBuf::from(path))),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match providers.len() {
+        0 => Arc::new(Ecb),
+        1 => providers.into_iter().next().unwrap(),
+        _ => Arc::new(Merged(providers)),
+    }
+}
+
+pub async fn dataset(provider: &dyn RateProvider) -> anyhow::Result<SharedDataset> {
+    let dataset = match provider.cached().await {
         // If we have no cached version of the dataset, download it
-        None => download_dataset().await?,
-        // Otherwise, read, parse, and return the cached version
-        Some(mut file) => {
-            let mut data = String::new();
-            file.read_to_string(&mut data).await?;
-
-            match parse_dataset(data).await {
-                Err(_) => download_dataset().await?,
-                Ok(dataset) => {
-                    let today = chrono::Utc::now().with_timezone(&Berlin).date_naive();
-
-                    // However, when the cached version is outdated, download a new one
-                    if let Some(true) = dataset.days.last().map(|day| day.date < today) {
-                        log::warn!("Dataset might be outdated, downloading a new one");
-                        download_dataset().await?
-                    } else {
-                        log::info!("Using cached dataset");
+        Ok(None) => download_dataset(provider).await?,
+        Err(e) => {
+            log::warn!("Cached dataset is unusable, downloading a new one: {}", e);
+            download_dataset(provider).await?
+        }
+        // Otherwise, use the cached version
+        Ok(Some(dataset)) => {
+            let today = today();
+
+            // However, when the cached version is outdated, download a new one
+            if let Some(true) = dataset.days.last().map(|day| day.date < today) {
+                log::warn!("Dataset might be outdated, downloading a new one");
+
+                // Outdated rates are still better than no rates at all
+                match download_dataset(provider).await {
+                    Ok(new_dataset) => new_dataset,
+                    Err(e) => {
+                        log::error!(
+                            "Failed to download dataset, using the cached one\n{:ident$}",
+                            e,
+                            ident = 2
+                        );
                         dataset
                     }
                 }
+            } else {
+                log::info!("Using cached dataset");
+                dataset
             }
         }
     };
 
-    Ok(Arc::new(RwLock::new(dataset)))
+    Ok(Arc::new(RwLock::new(Arc::new(dataset))))
 }
 
-pub async fn schedule_dataset_update(dataset: SharedDataset) {
-    let update_at = env::var("UPDATE_AT")
+pub async fn schedule_dataset_update(
+    provider: SharedProvider,
+    dataset: SharedDataset,
+    updates: DatasetUpdates,
+    last_update: SharedLastUpdate,
+) {
+    let update_at = *UPDATE_AT;
+
+    log::info!(
+        "Updates scheduled every day at {:02}:{:02} CET ({} minutes after midnight)",
+        update_at / 60,
+        update_at % 60,
+        update_at
+    );
+
+    let update_time = update_time();
+
+    // Seconds, so that a miscomputed schedule cannot download in a loop
+    let min_interval = env::var("MIN_UPDATE_INTERVAL")
         .ok()
-        .and_then(|s| s.parse::<u32>().ok())
-        // ECB rates are usually updated at 16:00 CET, but we use 18:00 CET,
-        // just to be sure we actually get the newest rates
-        .unwrap_or(18 * 60);
+        .and_then(|s| s.parse().ok())
+        .map_or(DEFAULT_MIN_UPDATE_INTERVAL, Duration::from_secs);
+
+    let mut last_started: Option<Instant> = None;
 
     loop {
-        let berlin_now = chrono::Utc::now().with_timezone(&Berlin);
-        let berlin_minute = berlin_now.hour() * 60 + berlin_now.minute();
-
-        // Check if we need to wait until tomorrow to prevent integer overflow
-        let next_update_in = if berlin_minute >= update_at {
-            // If it has been 18:00 CET, wait until the next day
-            berlin_minute + 24 * 60 - update_at
-        } else {
-            // Otherwise, wait until 18:00 CET today
-            update_at - berlin_minute
-        } as u64
-            * 60;
+        let berlin_now = now();
 
-        log::info!(
-            "Updates scheduled every day at {:02}:{:02} CET",
-            update_at / 60,
-            update_at % 60
+        // Sleep until the next update itself rather than counting minutes,
+        // so that oversleeping cannot make us skip a day
+        let next_update = next_update(berlin_now, update_time);
+
+        let mut next_update_in = (next_update - berlin_now).to_std().unwrap_or_default();
+
+        // Failed updates included, nothing starts again before the interval is over
+        if let Some(last_started) = last_started {
+            let floor = min_interval.saturating_sub(last_started.elapsed());
+
+            if next_update_in < floor {
+                log::warn!(
+                    "Next update would start {:?} after the last one, waiting {:?} instead",
+                    last_started.elapsed() + next_update_in,
+                    floor
+                );
+                next_update_in = floor;
+            }
+        }
+
+        log::debug!("Next update at {} (in {:?})", next_update, next_update_in);
+        tokio::time::sleep(next_update_in).await;
+
+        last_started = Some(Instant::now());
+
+        if let Err(e) = update_dataset(provider.as_ref(), &dataset, &updates, &last_update).await {
+            log::error!(
+                "Failed to update dataset, using yesterday's\n{:ident$}",
+                e,
+                ident = 2
+            );
+        }
+    }
+}
+
+/// Downloads a new dataset, swaps it in, and notifies the subscribers.
+/// Either way, the outcome is kept in `last_update`
+pub async fn update_dataset(
+    provider: &dyn RateProvider,
+    dataset: &SharedDataset,
+    updates: &DatasetUpdates,
+    last_update: &SharedLastUpdate,
+) -> anyhow::Result<()> {
+    let at = Utc::now();
+    let result = swap_dataset(provider, dataset, updates).await;
+
+    *last_update.write().await = Some(LastUpdate {
+        at,
+        added: result.as_ref().cloned().unwrap_or_default(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+    });
+
+    result.map(|_| ())
+}
+
+/// Returns the dates that were added by the new dataset
+async fn swap_dataset(
+    provider: &dyn RateProvider,
+    dataset: &SharedDataset,
+    updates: &DatasetUpdates,
+) -> anyhow::Result<Vec<NaiveDate>> {
+    // A snapshot, so that requests are not blocked while downloading
+    let current = snapshot(dataset).await;
+    let new_dataset = with_retries(|| provider.fetch_update(&current)).await?;
+
+    let mut lock = dataset.write().await;
+
+    if new_dataset.is_much_smaller_than(&lock) {
+        anyhow::bail!(
+            "Downloaded dataset has {} days, but the current one has {}",
+            new_dataset.days.len(),
+            lock.days.len()
         );
+    }
 
-        log::debug!("Next update in {} seconds", next_update_in);
-        tokio::time::sleep(Duration::from_secs(next_update_in)).await;
+    let added = new_dataset
+        .days
+        .iter()
+        .map(|day| day.date)
+        .filter(|date| lock.index_for_date(*date, Rounding::Exact).is_none())
+        .collect();
 
-        if update_at == berlin_minute {
-            match download_dataset().await {
-                Ok(new_dataset) => {
-                    let mut lock = dataset.write().await;
-                    *lock = new_dataset
-                }
-                Err(e) => log::error!(
-                    "Failed to update dataset, using yesterday's\n{:ident$}",
+    *lock = Arc::new(new_dataset);
+    drop(lock);
+
+    // Sending only fails when nobody is listening, which is fine
+    let _ = updates.send(());
+
+    Ok(added)
+}
+
+/// Reads `UPDATE_AT` as either minutes after midnight in Berlin or `HH:MM`,
+/// falling back to the default when it is missing or not a time of day
+fn update_at() -> u32 {
+    let Ok(value) = env::var("UPDATE_AT") else {
+        return DEFAULT_UPDATE_AT;
+    };
+
+    // Plain minutes first, to stay compatible with older configurations
+    let minutes = value.parse::<u32>().ok().or_else(|| {
+        NaiveTime::parse_from_str(&value, "%H:%M")
+            .ok()
+            .map(|time| time.hour() * 60 + time.minute())
+    });
+
+    match minutes {
+        Some(minutes) if minutes < 24 * 60 => minutes,
+        _ => {
+            log::warn!(
+                "Invalid UPDATE_AT `{}`, expected HH:MM or minutes after midnight (0-1439)",
+                value
+            );
+            DEFAULT_UPDATE_AT
+        }
+    }
+}
+
+/// `UPDATE_AT` is read just once, so that invalid values are only reported once
+static UPDATE_AT: LazyLock<u32> = LazyLock::new(update_at);
+
+fn update_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(*UPDATE_AT / 60, *UPDATE_AT % 60, 0)
+        .expect("`update_at` returns a time of day")
+}
+
+/// When the scheduler downloads the dataset next
+pub fn next_scheduled_update() -> DateTime<Tz> {
+    next_update(now(), update_time())
+}
+
+/// When the scheduler downloaded the dataset last, or would have
+pub fn last_scheduled_update() -> DateTime<Tz> {
+    next_scheduled_update() - chrono::Duration::days(1)
+}
+
+/// The first time after `now` when the clock in Berlin shows `update_time`.
+/// Works on whole `DateTime`s, so there is no minute arithmetic to underflow
+fn next_update(now: DateTime<Tz>, update_time: NaiveTime) -> DateTime<Tz> {
+    let today = now.date_naive();
+
+    [today, today + chrono::Duration::days(1)]
+        .into_iter()
+        .filter_map(|date| {
+            let update = date.and_time(update_time);
+
+            // When the clocks skip `update_time` (switching to summer time),
+            // update an hour later instead of skipping the whole day
+            update.and_local_timezone(Berlin).earliest().or_else(|| {
+                (update + chrono::Duration::hours(1))
+                    .and_local_timezone(Berlin)
+                    .earliest()
+            })
+        })
+        .find(|update| *update > now)
+        .unwrap_or(now + chrono::Duration::days(1))
+}
+
+/// Resolves the dataset URL from `DATASET_URL` or `DATASET_MODE`,
+/// falling back to the full history
+fn dataset_url() -> String {
+    if let Ok(url) = env::var("DATASET_URL") {
+        return url;
+    }
+
+    match env::var("DATASET_MODE").as_deref() {
+        Ok("daily") => DATASET_DAILY_URL,
+        Ok("90d") => DATASET_90D_URL,
+        Ok("hist") | Err(_) => DATASET_HIST_URL,
+        Ok(mode) => {
+            log::warn!("Unknown DATASET_MODE `{}`, using the full history", mode);
+            DATASET_HIST_URL
+        }
+    }
+    .to_string()
+}
+
+/// Downloads the dataset, retrying `DOWNLOAD_ATTEMPTS` times (3 by default)
+/// with exponential backoff
+async fn download_dataset(provider: &dyn RateProvider) -> anyhow::Result<Dataset> {
+    with_retries(|| provider.fetch()).await
+}
+
+async fn with_retries<'a, F>(fetch: F) -> anyhow::Result<Dataset>
+where
+    F: Fn() -> BoxFuture<'a, anyhow::Result<Dataset>>,
+{
+    let attempts = env::var("DOWNLOAD_ATTEMPTS")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(3)
+        .max(1);
+
+    let mut attempt = 1;
+
+    loop {
+        match fetch().await {
+            Ok(dataset) => return Ok(dataset),
+            Err(e) if attempt < attempts => {
+                // 2, 4, 8, … seconds, but never more than a minute
+                let backoff = Duration::from_secs(1 << attempt.min(6)).min(Duration::from_secs(60));
+
+                log::warn!(
+                    "Download attempt {} of {} failed, retrying in {} seconds\n{:ident$}",
+                    attempt,
+                    attempts,
+                    backoff.as_secs(),
                     e,
                     ident = 2
-                ),
+                );
+
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
             }
+            Err(e) => return Err(e),
         }
     }
 }
 
-async fn download_dataset() -> anyhow::Result<Dataset> {
-    log::info!("Downloading dataset");
+async fn download_ecb_dataset() -> anyhow::Result<Dataset> {
+    let url = dataset_url();
 
-    let response = reqwest::get(DATASET_HIST_URL).await?.text().await?;
+    log::info!("Downloading dataset from {}", url);
 
-    // Cache the response
-    if let Some(mut file) = cache_file().await {
-        let _ = file.write_all(response.as_bytes()).await;
-        let _ = file.flush().await;
-    }
+    let response = async { CLIENT.get(url).send().await?.text().await }
+        .await
+        .inspect_err(|_| metrics::FAILED_DOWNLOADS.inc())?;
+
+    let dataset = parse_dataset(response.clone())
+        .await
+        .inspect_err(|_| metrics::FAILED_DOWNLOADS.inc())?;
 
-    let dataset = parse_dataset(response).await;
+    // Cache the response only once we know it is valid,
+    // so that a broken download does not replace a good cache
+    let path = cache_path();
+    match write_cache(&path, &response).await {
+        Ok(()) => {
+            log::info!("Cached dataset in {}", path.display());
+
+            // The snapshot has to be newer than the cache to be used
+            if let Err(e) = write_snapshot(&dataset).await {
+                log::warn!("Could not write snapshot: {}", e);
+            }
+        }
+        Err(e) => log::warn!("Could not cache dataset: {}", e),
+    }
 
     log::info!("Downloaded dataset");
 
-    dataset
+    Ok(dataset)
+}
+
+/// The daily feed only makes sense on top of the longer ones, and a custom
+/// `DATASET_URL` needs `DATASET_DAILY_URL` to say where its daily feed is
+fn daily_url() -> Option<String> {
+    if let Ok(url) = env::var("DATASET_DAILY_URL") {
+        return Some(url);
+    }
+
+    match (env::var("DATASET_URL"), env::var("DATASET_MODE").as_deref()) {
+        (Err(_), Ok("hist" | "90d") | Err(_)) => Some(DATASET_DAILY_URL.to_string()),
+        _ => None,
+    }
+}
+
+/// The first weekday after the date, when the ECB publishes next barring holidays
+fn next_weekday(date: NaiveDate) -> NaiveDate {
+    let mut next = date + chrono::Duration::days(1);
+    while matches!(next.weekday(), Weekday::Sat | Weekday::Sun) {
+        next += chrono::Duration::days(1);
+    }
+
+    next
+}
+
+/// Appends the day from the daily feed to the current dataset. When that would
+/// leave a gap, e.g. after missed updates, the whole dataset is downloaded instead
+async fn update_ecb_dataset(current: &Dataset) -> anyhow::Result<Dataset> {
+    let (Some(url), Some(last_day)) = (daily_url(), current.days.last()) else {
+        return download_ecb_dataset().await;
+    };
+
+    log::info!("Downloading the latest day from {}", url);
+
+    let response = async { CLIENT.get(&url).send().await?.text().await }
+        .await
+        .inspect_err(|_| metrics::FAILED_DOWNLOADS.inc())?;
+
+    let daily = parse_dataset(response)
+        .await
+        .inspect_err(|_| metrics::FAILED_DOWNLOADS.inc())?;
+
+    // Unwrapping is safe because `parse_dataset` fails without days
+    let first_day = daily.days.first().unwrap();
+    if first_day.date > next_weekday(last_day.date) {
+        log::info!(
+            "The daily feed skips the days after {}, downloading the whole dataset",
+            last_day.date
+        );
+        return download_ecb_dataset().await;
+    }
+
+    // The daily feed wins, in case the ECB corrected a rate
+    let mut dataset = current.clone();
+    for day in daily.days.iter() {
+        let rates = daily
+            .currencies
+            .iter()
+            .zip(day.rates.iter())
+            .filter_map(|(code, rate)| Some((&**code, (*rate)?)))
+            .collect::<Vec<_>>();
+
+        dataset.insert_day(day.date, &rates);
+    }
+
+    // Only the snapshot is updated, the XML cache keeps the last full download
+    if let Err(e) = write_snapshot(&dataset).await {
+        log::warn!("Could not write snapshot: {}", e);
+    }
+
+    log::info!("Updated dataset to {}", dataset.days.last().unwrap().date);
+
+    Ok(dataset)
+}
+
+/// Reads the allow-list of currencies from `CURRENCIES`, e.g. `USD,GBP,JPY`.
+/// Without it, every currency in the dataset is kept
+fn allowed_currencies() -> Option<HashSet<String>> {
+    let currencies = env::var("CURRENCIES").ok()?;
+
+    Some(
+        currencies
+            .split(',')
+            .map(|c| c.trim().to_uppercase())
+            .filter(|c| !c.is_empty())
+            .collect(),
+    )
+}
+
+/// Reads synthetic currencies with a fixed rate to EUR from `PEGGED_CURRENCIES`.
+/// The format is a comma-separated list of `CODE:RATE` pairs, where the rate is
+/// the amount of the currency one euro buys, e.g. `BAM:1.95583,XOF:655.957`
+fn pegged_currencies() -> Vec<(String, f64)> {
+    let Ok(pegs) = env::var("PEGGED_CURRENCIES") else {
+        return Vec::new();
+    };
+
+    pegs.split(',')
+        .map(str::trim)
+        .filter(|peg| !peg.is_empty())
+        .filter_map(|peg| {
+            let parsed = peg.split_once(':').and_then(|(code, rate)| {
+                let rate = rate.trim().parse::<f64>().ok()?;
+                let code = code.trim().to_uppercase();
+
+                (code.len() == 3 && rate.is_finite() && rate > 0.0).then_some((code, rate))
+            });
+
+            if parsed.is_none() {
+                log::warn!("Ignoring invalid peg `{}`, expected CODE:RATE", peg);
+            }
+
+            parsed
+        })
+        .collect()
+}
+
+/// Builds the shared currency codes of a dataset
+pub fn shared_currencies(currencies: Vec<String>) -> Arc<[Currency]> {
+    currencies.into_iter().map(Currency::from).collect()
+}
+
+/// A day as it is read from the feed, the rates refer to the currencies by
+/// the order they were first seen in, so that the codes are only stored once
+struct ParsedDay {
+    date: NaiveDate,
+    rates: Vec<(usize, Option<f64>)>,
+}
+
+/// Reads the attribute of a `Cube` element, if it is there
+fn attribute(element: &BytesStart, name: &str) -> anyhow::Result<Option<String>> {
+    match element.try_get_attribute(name)? {
+        Some(attribute) => Ok(Some(attribute.unescape_value()?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Non-numeric rates are unavailable instead of failing the whole dataset,
+/// the ECB publishes `N/A` for suspended currencies. So are rates that are not
+/// positive, which would divide by zero (or flip signs) as a base currency
+fn parse_rate(rate: Option<&str>) -> Option<f64> {
+    rate?
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
 }
 
 async fn parse_dataset(data: String) -> anyhow::Result<Dataset> {
     tokio::task::spawn_blocking(move || {
-        let xml_document: XmlDocument = quick_xml::de::from_str(&data)?;
+        let allowed = allowed_currencies();
 
-        let mut currencies = HashSet::new();
+        // The currency codes in the order they were first seen
+        let mut seen = Vec::<String>::new();
+        let mut seen_index = HashMap::<String, usize>::new();
 
-        // Fill the currencies `HashSet`
-        currencies.insert("EUR".to_string());
-        for day in xml_document.data.days.iter() {
-            for rate in day.rates.iter() {
-                currencies.insert(rate.currency.clone());
+        let mut parsed_days = Vec::<ParsedDay>::new();
+
+        // The feed nests the rates in days and the days in a single `Cube`,
+        // so the depth tells what an element is
+        let mut depth = 0;
+        // Rates of days with a malformed date are skipped along with them
+        let mut in_valid_day = false;
+
+        let mut reader = Reader::from_str(&data);
+        loop {
+            let (element, is_empty) = match reader.read_event()? {
+                Event::Start(element) => (element, false),
+                Event::Empty(element) => (element, true),
+                Event::End(element) if element.local_name().as_ref() == b"Cube" => {
+                    depth -= 1;
+                    continue;
+                }
+                Event::Eof => break,
+                _ => continue,
+            };
+
+            if element.local_name().as_ref() != b"Cube" {
+                continue;
+            }
+
+            match depth {
+                // A day,
+                1 => {
+                    let date = attribute(&element, "time")?;
+                    match date.as_deref().map(str::trim).map(NaiveDate::from_str) {
+                        Some(Ok(date)) => {
+                            parsed_days.push(ParsedDay {
+                                date,
+                                rates: Vec::new(),
+                            });
+                            in_valid_day = true;
+                        }
+                        _ => {
+                            log::warn!("Skipping a day with an invalid date: {:?}", date);
+                            in_valid_day = false;
+                        }
+                    }
+                }
+                // or a rate of the last day, which skips the currencies not allowed
+                2 if in_valid_day => {
+                    let Some(currency) = attribute(&element, "currency")? else {
+                        continue;
+                    };
+                    if allowed.as_ref().is_some_and(|a| !a.contains(&currency)) {
+                        continue;
+                    }
+
+                    let rate = parse_rate(attribute(&element, "rate")?.as_deref());
+                    let index = *seen_index.entry(currency.clone()).or_insert_with(|| {
+                        seen.push(currency);
+                        seen.len() - 1
+                    });
+
+                    if let Some(day) = parsed_days.last_mut() {
+                        day.rates.push((index, rate));
+                    }
+                }
+                _ => {}
+            }
+
+            if !is_empty {
+                depth += 1;
             }
         }
 
+        anyhow::ensure!(
+            !parsed_days.is_empty(),
+            "The dataset does not contain any valid days"
+        );
+
+        // Fill the currencies `HashSet`, EUR is always included
+        let mut currencies = seen.iter().cloned().collect::<HashSet<_>>();
+        currencies.insert("EUR".to_string());
+
+        // Published rates take precedence over pegs
+        let pegs = pegged_currencies()
+            .into_iter()
+            .filter(|(code, _)| {
+                let published = currencies.contains(code);
+                if published {
+                    log::warn!("Ignoring the peg of {}, the ECB publishes it", code);
+                }
+                !published
+            })
+            .collect::<Vec<_>>();
+        currencies.extend(pegs.iter().map(|(code, _)| code.clone()));
+
         // Turn the currencies into a `Vec` and sort them
         let mut currencies = currencies.into_iter().collect::<Vec<String>>();
         currencies.sort();
 
-        // Unwrapping is safe because we add it to the `HashSet` above
+        // Unwrapping is safe because we add them to the `HashSet` above
         let eur_index = currencies.binary_search(&"EUR".to_string()).unwrap();
+        let seen_to_sorted = seen
+            .iter()
+            .map(|code| currencies.binary_search(code).unwrap())
+            .collect::<Vec<_>>();
 
-        let mut days = Vec::new();
-
-        // For every day,
-        for mut xml_day in xml_document.data.days {
-            let mut day = Day {
-                date: xml_day.date,
-                rates: vec![None; currencies.len()],
-            };
-
-            // sort the rates,
-            xml_day.rates.sort_by_key(|rate| rate.currency.clone());
+        // For every day, set the Euro rate to 1.0 along with the pegs,
+        // and then set all the published rates
+        let mut days = parsed_days
+            .into_iter()
+            .map(|parsed_day| {
+                let mut day = Day {
+                    date: parsed_day.date,
+                    rates: vec![None; currencies.len()],
+                };
 
-            // and set the Euro rate to 1.0,
-            day.rates[eur_index] = Some(1.0);
-            for rate in xml_day.rates {
-                // and then set all supported currencies
-                if let Ok(index) = currencies.binary_search(&&rate.currency) {
-                    day.rates[index] = Some(rate.rate);
+                day.rates[eur_index] = Some(1.0);
+                for (code, rate) in pegs.iter() {
+                    if let Ok(index) = currencies.binary_search(code) {
+                        day.rates[index] = Some(*rate);
+                    }
+                }
+                for (index, rate) in parsed_day.rates {
+                    day.rates[seen_to_sorted[index]] = rate;
                 }
-            }
 
-            days.push(day);
-        }
+                day
+            })
+            .collect::<Vec<_>>();
 
         // Reverse the days so that the oldest day is first
         days.reverse();
 
-        // Build a static slice of static currency codes
-        let currencies: &'static [&str] = currencies
-            .into_iter()
-            .map(|c| -> &'static str { c.leak() })
-            .collect::<Vec<_>>()
-            .leak();
-
-        Ok(Dataset { days, currencies })
+        Ok(Dataset::new(days, shared_currencies(currencies)))
     })
     .await?
 }
 
-#[derive(Debug, Deserialize)]
-struct XmlDocument {
-    #[serde(rename = "Cube")]
-    data: XmlCube,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[derive(Debug, Deserialize)]
-struct XmlCube {
-    #[serde(rename = "Cube")]
-    days: Vec<XmlDay>,
-}
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
 
-#[derive(Debug, Deserialize)]
-struct XmlDay {
-    #[serde(rename = "@time")]
-    date: NaiveDate,
-    #[serde(rename = "$value")]
-    rates: Vec<XmlRate>,
-}
+    /// A dataset of EUR, JPY, and USD, in that order
+    fn dataset(days: &[(u32, [Option<f64>; 3])]) -> Dataset {
+        let days = days
+            .iter()
+            .map(|(day, rates)| Day {
+                date: date(*day),
+                rates: rates.to_vec(),
+            })
+            .collect();
+
+        Dataset::new(
+            days,
+            shared_currencies(vec!["EUR".into(), "JPY".into(), "USD".into()]),
+        )
+    }
+
+    #[test]
+    fn cross_rate_between_two_non_eur_currencies() {
+        let dataset = dataset(&[(2, [Some(1.0), Some(165.0), Some(1.1)])]);
+        let day = &dataset.days[0];
+
+        let usd = day.convert("USD", &dataset).unwrap();
+        let jpy_per_usd = usd.rate("JPY", &dataset).unwrap();
+
+        // JPY/EUR divided by USD/EUR, without a detour through EUR
+        assert!((jpy_per_usd - 150.0).abs() < 1e-9);
+        assert_eq!(usd.rate("USD", &dataset), Some(1.0));
+        assert!((usd.rate("EUR", &dataset).unwrap() - 1.0 / 1.1).abs() < 1e-12);
+
+        let decimal = day.convert_decimal("USD", &dataset).unwrap();
+        assert_eq!(decimal[1], Decimal::from_str("150").ok());
+    }
+
+    #[test]
+    fn unavailable_base_currency_cannot_convert() {
+        let dataset = dataset(&[(2, [Some(1.0), Some(165.0), None])]);
+
+        assert!(dataset.days[0].convert("USD", &dataset).is_none());
+    }
+
+    #[test]
+    fn parse_rate_rejects_unusable_rates() {
+        assert_eq!(parse_rate(Some(" 1.0721 ")), Some(1.0721));
+        assert_eq!(parse_rate(Some("N/A")), None);
+        assert_eq!(parse_rate(Some("0")), None);
+        assert_eq!(parse_rate(Some("-1.5")), None);
+        assert_eq!(parse_rate(Some("inf")), None);
+        assert_eq!(parse_rate(None), None);
+    }
+
+    /// Friday the 5th to Friday the 12th of January 2024, without the
+    /// weekend and a holiday on Wednesday the 10th
+    fn week() -> Dataset {
+        let rates = [Some(1.0), Some(160.0), Some(1.1)];
+
+        dataset(&[(5, rates), (8, rates), (9, rates), (11, rates), (12, rates)])
+    }
+
+    fn dates(dataset: &Dataset, start: Option<u32>, end: Option<u32>) -> Vec<u32> {
+        let range = dataset.timeframe_range(start.map(date), end.map(date));
+
+        dataset.days[range]
+            .iter()
+            .map(|day| day.date.day())
+            .collect()
+    }
+
+    #[test]
+    fn timeframe_range_stays_inside_the_window() {
+        let dataset = week();
+
+        assert_eq!(dates(&dataset, Some(8), Some(11)), [8, 9, 11]);
+        // A start on a weekend or a holiday moves forward
+        assert_eq!(dates(&dataset, Some(6), Some(9)), [8, 9]);
+        assert_eq!(dates(&dataset, Some(10), Some(12)), [11, 12]);
+        // An end on a weekend or a holiday moves back
+        assert_eq!(dates(&dataset, Some(5), Some(7)), [5]);
+        assert_eq!(dates(&dataset, Some(9), Some(10)), [9]);
+        assert_eq!(dates(&dataset, Some(6), Some(10)), [8, 9]);
+    }
+
+    #[test]
+    fn timeframe_range_with_open_ends() {
+        let dataset = week();
+
+        assert_eq!(dates(&dataset, None, None), [5, 8, 9, 11, 12]);
+        assert_eq!(dates(&dataset, Some(9), None), [9, 11, 12]);
+        assert_eq!(dates(&dataset, None, Some(8)), [5, 8]);
+    }
+
+    #[test]
+    fn timeframe_range_without_days_inside_is_empty() {
+        let dataset = week();
+
+        // Only the weekend, or only the holiday
+        assert!(dates(&dataset, Some(6), Some(7)).is_empty());
+        assert!(dates(&dataset, Some(10), Some(10)).is_empty());
+        // Entirely before or after the dataset
+        assert!(dates(&dataset, Some(1), Some(4)).is_empty());
+        assert!(dates(&dataset, None, Some(4)).is_empty());
+        assert!(dates(&dataset, Some(13), Some(20)).is_empty());
+        assert!(dates(&dataset, Some(13), None).is_empty());
+    }
+
+    #[test]
+    fn timeframe_range_ends_at_the_last_day() {
+        let dataset = week();
+        let len = dataset.days.len();
+
+        // Ending on the last day, or after it, stays within the days
+        assert_eq!(
+            dataset.timeframe_range(Some(date(9)), Some(date(12))),
+            2..len
+        );
+        assert_eq!(
+            dataset.timeframe_range(Some(date(9)), Some(date(31))),
+            2..len
+        );
+        assert_eq!(dates(&dataset, Some(12), Some(12)), [12]);
+        assert_eq!(dates(&dataset, Some(12), Some(31)), [12]);
+    }
+
+    #[test]
+    fn series_only_covers_the_range() {
+        let dataset = week();
+        let range = dataset.timeframe_range(Some(date(8)), Some(date(9)));
+
+        assert_eq!(
+            dataset.series("USD", range).collect::<Vec<_>>(),
+            [(date(8), Some(1.1)), (date(9), Some(1.1))]
+        );
+        assert_eq!(dataset.series("USD", 4..9).count(), 0);
+        assert_eq!(dataset.series("XXX", 0..5).count(), 0);
+    }
+
+    #[test]
+    fn insert_day_adds_a_new_currency_to_every_day() {
+        let mut dataset = dataset(&[
+            (2, [Some(1.0), Some(165.0), Some(1.1)]),
+            (3, [Some(1.0), Some(166.0), Some(1.2)]),
+        ]);
+
+        // CHF sorts before every other currency, so every rate moves
+        dataset.insert_day(date(4), &[("EUR", 1.0), ("CHF", 0.9), ("USD", 1.3)]);
+
+        assert_eq!(
+            &*dataset.currencies,
+            ["CHF", "EUR", "JPY", "USD"].map(Currency::from)
+        );
+        assert!(dataset.days.iter().all(|day| day.rates.len() == 4));
+        assert_eq!(dataset.days[0].rate("CHF", &dataset), None);
+        assert_eq!(dataset.days[0].rate("JPY", &dataset), Some(165.0));
+        assert_eq!(dataset.days[1].rate("USD", &dataset), Some(1.2));
+        assert_eq!(dataset.days[2].rate("CHF", &dataset), Some(0.9));
+        assert_eq!(dataset.days[2].rate("JPY", &dataset), None);
+        assert_eq!(
+            dataset.series("CHF", 0..3).collect::<Vec<_>>(),
+            [(date(2), None), (date(3), None), (date(4), Some(0.9))]
+        );
+    }
+
+    #[test]
+    fn insert_day_keeps_the_days_in_order() {
+        let mut dataset = dataset(&[
+            (2, [Some(1.0), Some(165.0), Some(1.1)]),
+            (5, [Some(1.0), Some(168.0), Some(1.4)]),
+        ]);
+
+        // A new day in between, with a new currency sorting last
+        dataset.insert_day(date(3), &[("EUR", 1.0), ("ZAR", 20.0)]);
+        // An existing day is updated in place
+        dataset.insert_day(date(5), &[("JPY", 169.0)]);
 
-#[derive(Debug, Deserialize)]
-struct XmlRate {
-    #[serde(rename = "@currency")]
-    currency: String,
-    #[serde(rename = "@rate")]
-    rate: f64,
+        let dates = dataset.days.iter().map(|day| day.date).collect::<Vec<_>>();
+        assert_eq!(dates, [date(2), date(3), date(5)]);
+        assert_eq!(dataset.index_of("ZAR"), Some(3));
+        assert_eq!(dataset.days[1].rate("ZAR", &dataset), Some(20.0));
+        assert_eq!(dataset.days[1].rate("USD", &dataset), None);
+        assert_eq!(dataset.days[2].rate("JPY", &dataset), Some(169.0));
+        assert_eq!(dataset.days[2].rate("USD", &dataset), Some(1.4));
+        assert_eq!(dataset.days[2].rate("ZAR", &dataset), None);
+    }
+
+    /// Two days of the ECB feed, with RUB suspended on the later one
+    const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gesmes:Envelope xmlns:gesmes="http://www.gesmes.org/xml/2002-08-01" xmlns="http://www.ecb.int/vocabulary/2002-08-01/eurofxref">
+    <Cube>
+        <Cube time="2024-01-03">
+            <Cube currency="USD" rate="1.0919"/>
+            <Cube currency="RUB" rate="N/A"/>
+        </Cube>
+        <Cube time="2024-01-02">
+            <Cube currency="USD" rate="1.0956"/>
+            <Cube currency="RUB" rate="99.5"/>
+        </Cube>
+    </Cube>
+</gesmes:Envelope>"#;
+
+    #[tokio::test]
+    async fn parse_dataset_with_unavailable_rates() {
+        let dataset = parse_dataset(FEED.to_string()).await.unwrap();
+
+        assert_eq!(dataset.timeframe(), Some([date(2), date(3)]));
+        assert_eq!(
+            dataset.series("RUB", 0..2).collect::<Vec<_>>(),
+            [(date(2), Some(99.5)), (date(3), None)]
+        );
+        assert_eq!(dataset.days[1].rate("USD", &dataset), Some(1.0919));
+        assert_eq!(dataset.days[1].rate("EUR", &dataset), Some(1.0));
+    }
+
+    fn berlin(day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        use chrono::TimeZone;
+
+        Berlin
+            .with_ymd_and_hms(2024, 3, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn minutes(minutes: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(minutes / 60, minutes % 60, 0).unwrap()
+    }
+
+    #[test]
+    fn next_update_at_midnight() {
+        // Late in the day, midnight is the next day
+        assert_eq!(next_update(berlin(5, 23, 59), minutes(0)), berlin(6, 0, 0));
+        // Right at midnight, the update is a day away
+        assert_eq!(next_update(berlin(5, 0, 0), minutes(0)), berlin(6, 0, 0));
+    }
+
+    #[test]
+    fn next_update_at_the_last_minute() {
+        assert_eq!(
+            next_update(berlin(5, 0, 0), minutes(1439)),
+            berlin(5, 23, 59)
+        );
+        assert_eq!(
+            next_update(berlin(5, 23, 59), minutes(1439)),
+            berlin(6, 23, 59)
+        );
+    }
+
+    #[test]
+    fn next_update_when_the_clocks_skip_it() {
+        // 02:30 does not exist on the last Sunday of March in Berlin
+        assert_eq!(
+            next_update(berlin(30, 18, 0), minutes(150)),
+            berlin(31, 3, 30)
+        );
+    }
+
+    #[tokio::test]
+    async fn replaced_datasets_free_their_currencies() {
+        let mut lists = Vec::new();
+        let mut codes = Vec::new();
+
+        for _ in 0..3 {
+            let dataset = parse_dataset(FEED.to_string()).await.unwrap();
+            lists.push(Arc::downgrade(&dataset.currencies));
+            codes.extend(dataset.currencies.iter().map(Arc::downgrade));
+        }
+
+        // Nothing outlives the datasets, not even a single currency code
+        assert!(lists.iter().all(|list| list.upgrade().is_none()));
+        assert!(codes.iter().all(|code| code.upgrade().is_none()));
+    }
+
+    /// A directory of its own for every test, which does not exist yet
+    fn scratch_directory(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("exchangerates-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        directory
+    }
+
+    #[tokio::test]
+    async fn write_cache_creates_the_directory() {
+        let directory = scratch_directory("write-cache");
+        let path = directory.join("nested").join("dataset.xml");
+
+        write_cache(&path, FEED).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), FEED);
+        assert_eq!(
+            std::fs::read_to_string(checksum_path(&path)).unwrap(),
+            checksum(FEED)
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn shorter_cache_replaces_a_longer_one() {
+        let directory = scratch_directory("shorter-cache");
+        let path = directory.join("dataset.xml");
+        let long = FEED.repeat(10);
+
+        write_cache(&path, &long).await.unwrap();
+        write_cache(&path, FEED).await.unwrap();
+
+        // Nothing of the longer payload is left over at the end
+        let data = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(data, FEED);
+        verify_cache(&path, &data).await.unwrap();
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// A provider that only has a day of its own with `fetch`, and appends
+    /// the 4th to the current dataset with `fetch_update`
+    struct Appending(u32);
+
+    impl RateProvider for Appending {
+        fn fetch(&self) -> BoxFuture<'_, anyhow::Result<Dataset>> {
+            let day = self.0;
+            Box::pin(async move { Ok(dataset(&[(day, [Some(1.0), Some(160.0), None])])) })
+        }
+
+        fn cached(&self) -> BoxFuture<'_, anyhow::Result<Option<Dataset>>> {
+            Box::pin(async { Ok(None) })
+        }
+
+        fn source(&self) -> String {
+            "appending".to_string()
+        }
+
+        fn fetch_update<'a>(
+            &'a self,
+            current: &'a Dataset,
+        ) -> BoxFuture<'a, anyhow::Result<Dataset>> {
+            Box::pin(async {
+                let mut dataset = current.clone();
+                dataset.insert_day(date(4), &[("EUR", 1.0), ("USD", 1.2)]);
+                Ok(dataset)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn merged_providers_update_incrementally() {
+        let merged = Merged(vec![
+            Arc::new(Appending(2)),
+            Arc::new(XmlFile(PathBuf::new())),
+        ]);
+        let current = dataset(&[
+            (2, [Some(1.0), Some(160.0), None]),
+            (3, [Some(1.0), Some(161.0), None]),
+        ]);
+
+        // The file cannot be read, so only the update of the other one is merged
+        let updated = merged.fetch_update(&current).await.unwrap();
+
+        let dates = updated.days.iter().map(|day| day.date).collect::<Vec<_>>();
+        assert_eq!(dates, [date(2), date(3), date(4)]);
+        assert_eq!(updated.days[1].rate("JPY", &updated), Some(161.0));
+        assert_eq!(updated.days[2].rate("USD", &updated), Some(1.2));
+    }
+
+    /// Converting the full history (about 6,700 days of 42 currencies since
+    /// 1999) to USD, as `timeframe` did before `convert_days` and as it does now
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_convert_full_history() {
+        use std::{hint::black_box, time::Instant};
+
+        // Stand-ins for the other currencies, with EUR and USD among them
+        let mut currencies = (b'A'..=b'T')
+            .flat_map(|a| [format!("{}AX", a as char), format!("{}BX", a as char)])
+            .chain(["EUR".to_string(), "USD".to_string()])
+            .collect::<Vec<_>>();
+        currencies.sort();
+
+        let start = NaiveDate::from_ymd_opt(1999, 1, 4).unwrap();
+        let days = start
+            .iter_days()
+            .filter(|date| date.weekday().number_from_monday() <= 5)
+            .take(6_700)
+            .enumerate()
+            .map(|(index, date)| Day {
+                date,
+                rates: (0..currencies.len())
+                    .map(|c| Some(1.0 + (index + c) as f64 / 1e4))
+                    .collect(),
+            })
+            .collect();
+        let dataset = Dataset::new(days, shared_currencies(currencies));
+
+        let time = |name: &str, convert: &dyn Fn() -> f64| {
+            let runs = 50;
+            let started = Instant::now();
+            for _ in 0..runs {
+                black_box(convert());
+            }
+            println!("{}: {:.2?} per conversion", name, started.elapsed() / runs);
+        };
+
+        time("cloning and looking up the base every day", &|| {
+            dataset
+                .days
+                .iter()
+                .filter_map(|day| {
+                    let day = day.clone();
+                    let from = dataset
+                        .currencies
+                        .binary_search_by(|c| (**c).cmp("USD"))
+                        .ok()?;
+                    day.convert_by_index(from)
+                })
+                .map(|day| black_box(day).rates.iter().flatten().sum::<f64>())
+                .sum()
+        });
+        time("convert_days", &|| {
+            dataset
+                .convert_days(&dataset.days, "USD")
+                .map(|day| black_box(day).rates.iter().flatten().sum::<f64>())
+                .sum()
+        });
+    }
 }
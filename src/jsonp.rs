@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use poem::{
+    http::{header, Method, StatusCode},
+    Body, Endpoint, IntoResponse, Request, Response,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Params {
+    callback: Option<String>,
+}
+
+/// Anything else could inject script into the page embedding the response
+fn is_valid_callback(callback: &str) -> bool {
+    !callback.is_empty()
+        && callback.len() <= 64
+        && callback
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Wraps JSON responses of GET `/rate` and `/rates*` in `callback(…)` when the
+/// `callback` query parameter is given, for clients that cannot use CORS
+pub async fn wrap_callback<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    let callback = match req.params::<Params>() {
+        Ok(Params {
+            callback: Some(callback),
+        }) if req.method() == Method::GET && req.uri().path().starts_with("/rate") => callback,
+        _ => return ep.call(req).await.map(IntoResponse::into_response),
+    };
+
+    if !is_valid_callback(&callback) {
+        return Err(poem::Error::from_string(
+            "Invalid callback, only letters, digits, and underscores are allowed",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let resp = ep.call(req).await?.into_response();
+
+    let is_json = resp
+        .content_type()
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    if !is_json {
+        return Ok(resp);
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let json = body.into_string().await?;
+
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        "application/javascript; charset=utf-8"
+            .parse()
+            .expect("the content type is a valid header value"),
+    );
+    parts.headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        "nosniff".parse().expect("nosniff is a valid header value"),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    // The empty comment keeps the response from starting with the callback,
+    // which some plugins would otherwise sniff as something else
+    let script = format!("/**/{}({});", callback, json);
+
+    Ok(Response::from_parts(parts, Body::from_string(script)))
+}
@@ -0,0 +1,127 @@
+use std::{env, io::Write, sync::Arc, time::Instant};
+
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use poem::{Endpoint, IntoResponse, Request, Response};
+use serde::Deserialize;
+use serde_json::{Map, Value as Json};
+
+/// Collects the key-values of a record into a JSON object
+struct Fields(Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = serde_json::to_value(value).map_err(kv::Error::boxed)?;
+
+        // Missing values are left out rather than logged as `null`
+        if !value.is_null() {
+            self.0.insert(key.to_string(), value);
+        }
+
+        Ok(())
+    }
+}
+
+fn json_line(record: &log::Record) -> String {
+    let mut fields = Fields(Map::new());
+    fields.0.insert(
+        "timestamp".to_string(),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into(),
+    );
+    fields
+        .0
+        .insert("level".to_string(), record.level().as_str().into());
+    fields
+        .0
+        .insert("target".to_string(), record.target().into());
+    fields
+        .0
+        .insert("message".to_string(), record.args().to_string().into());
+
+    if let Err(e) = record.key_values().visit(&mut fields) {
+        fields
+            .0
+            .insert("fields_error".to_string(), e.to_string().into());
+    }
+
+    Json::Object(fields.0).to_string()
+}
+
+/// Logs in the pretty format, or as JSON lines with `LOG_FORMAT=json`.
+/// Either way, `RUST_LOG` sets the level
+pub fn init() {
+    let mut builder = pretty_env_logger::formatted_builder();
+
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+
+    if env::var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json")) {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record)));
+    }
+
+    builder.init();
+}
+
+/// The base currency is only picked up from the query string, it is
+/// in the path or the body for the other endpoints
+#[derive(Deserialize)]
+struct Params {
+    from: Option<String>,
+    base: Option<String>,
+}
+
+/// Logs the method, path, status, and latency of every request. The query
+/// string is only logged with `debug`, since it can get long for timeframes
+pub async fn log_requests<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let base_currency = req
+        .params::<Params>()
+        .ok()
+        .and_then(|params| params.from.or(params.base))
+        .map(|base| base.trim().to_uppercase());
+    let start = Instant::now();
+
+    let res = ep.call(req).await.map(IntoResponse::into_response);
+
+    let status = match &res {
+        Ok(resp) => resp.status(),
+        Err(err) => err.status(),
+    };
+    let latency = start.elapsed();
+    let latency_ms = latency.as_secs_f64() * 1000.0;
+
+    if log::log_enabled!(log::Level::Debug) {
+        log::debug!(
+            method = method.as_str(),
+            path = uri.path(),
+            query = uri.query(),
+            status = status.as_u16(),
+            latency_ms,
+            base_currency;
+            "{} {} {} {:.2?}",
+            method,
+            uri,
+            status.as_u16(),
+            latency
+        );
+    } else {
+        log::info!(
+            method = method.as_str(),
+            path = uri.path(),
+            status = status.as_u16(),
+            latency_ms,
+            base_currency;
+            "{} {} {} {:.2?}",
+            method,
+            uri.path(),
+            status.as_u16(),
+            latency
+        );
+    }
+
+    res
+}
@@ -1,33 +1,302 @@
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
-use poem::{listener::TcpListener, EndpointExt, Route};
+use futures::StreamExt;
+use poem::{
+    error::SizedLimitError,
+    get,
+    http::{header, Method, StatusCode},
+    listener::TcpListener,
+    middleware::{Compression, Cors, SizeLimit},
+    Endpoint, EndpointExt, IntoResponse, Middleware, Request, Response, Route,
+};
 use poem_openapi::OpenApiService;
-use tokio::signal::ctrl_c;
+use tokio::{signal::ctrl_c, sync::broadcast};
 
 mod api;
+mod auth;
+mod caching;
 mod data;
+mod jsonp;
+mod logging;
+mod metrics;
+mod ratelimit;
+mod store;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    pretty_env_logger::init();
+/// Request bodies are only small JSON objects, anything larger is rejected
+const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// How long in-flight requests may take to finish on shutdown
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Rejects request bodies over `max_body_size` bytes with 413. `SizeLimit`
+/// checks the `Content-Length` but refuses requests without one, so chunked
+/// bodies are read up to the limit instead, and requests without a body pass
+async fn limit_body_size<E: Endpoint>(
+    ep: Arc<E>,
+    mut req: Request,
+    max_body_size: usize,
+) -> poem::Result<Response> {
+    if req.headers().contains_key(header::CONTENT_LENGTH) {
+        return SizeLimit::new(max_body_size)
+            .transform(ep)
+            .call(req)
+            .await
+            .map(IntoResponse::into_response);
+    }
+
+    if req.headers().contains_key(header::TRANSFER_ENCODING) {
+        let mut body = Vec::new();
+        let mut chunks = req.take_body().into_bytes_stream();
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| poem::Error::new(e, StatusCode::BAD_REQUEST))?;
+            if body.len() + chunk.len() > max_body_size {
+                return Err(SizedLimitError::PayloadTooLarge.into());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        req.set_body(body);
+    }
+
+    ep.call(req).await.map(IntoResponse::into_response)
+}
+
+/// Reads the address to listen on from `BIND`, or `HOST` and `PORT`,
+/// defaulting to 0.0.0.0:8000
+fn bind_address() -> Result<SocketAddr, String> {
+    if let Ok(bind) = env::var("BIND") {
+        return bind
+            .parse()
+            .map_err(|e| format!("Invalid BIND `{}`: {}", bind, e));
+    }
+
+    let host = match env::var("HOST") {
+        Ok(host) => host
+            .parse()
+            .map_err(|e| format!("Invalid HOST `{}`: {}", host, e))?,
+        Err(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+
+    let port = match env::var("PORT") {
+        Ok(port) => port
+            .parse()
+            .map_err(|e| format!("Invalid PORT `{}`: {}", port, e))?,
+        Err(_) => 8000,
+    };
+
+    Ok(SocketAddr::new(host, port))
+}
+
+/// Resolves on SIGINT or, on Unix, SIGTERM, which is what containers get stopped with
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                log::warn!("Could not listen for SIGTERM: {}", e);
+                ctrl_c().await.unwrap_or(());
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    ctrl_c().await.unwrap_or(());
+
+    log::info!("Shutting down");
+}
+
+/// Reads the number of runtime worker threads from `WORKER_THREADS`,
+/// `None` for one per CPU core
+fn worker_threads() -> Result<Option<usize>, String> {
+    match env::var("WORKER_THREADS") {
+        Ok(threads) => match threads.parse() {
+            Ok(threads) if threads > 0 => Ok(Some(threads)),
+            _ => Err(format!("Invalid WORKER_THREADS `{}`", threads)),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    logging::init();
+
+    // Always multi-threaded, even with a single worker, since SQLite
+    // blocks in place, which the current-thread runtime does not support
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+
+    match worker_threads() {
+        Ok(Some(threads)) => {
+            runtime.worker_threads(threads);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    runtime.build()?.block_on(serve())
+}
+
+async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    // Check the address before downloading anything, so that typos fail fast
+    let socket_addr = bind_address().unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    });
+
+    let provider = store::wrap(data::provider());
 
     // Download dataset or use a cached one
-    let dataset = data::dataset().await?;
+    let dataset = data::dataset(provider.as_ref()).await?;
+
+    // Subscribers of `/rates/stream` get notified through this channel
+    let (updates, _) = broadcast::channel(16);
+
+    // `/admin/last-update` reports what the scheduler did last
+    let last_update = data::SharedLastUpdate::default();
 
     // Schedule dataset updates
-    tokio::spawn(data::schedule_dataset_update(dataset.clone()));
+    tokio::spawn(data::schedule_dataset_update(
+        provider.clone(),
+        dataset.clone(),
+        updates.clone(),
+        last_update.clone(),
+    ));
 
     let service =
         OpenApiService::new(api::Api, "Exchange rates API", "1.0").server("https://exchange.rates");
 
+    // Any origin can call the API, unless `CORS_ORIGINS` lists the allowed ones
+    let cors = env::var("CORS_ORIGINS")
+        .iter()
+        .flat_map(|origins| origins.split(','))
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .fold(
+            Cors::new().allow_methods([Method::GET, Method::POST]),
+            |cors, origin| cors.allow_origin(origin),
+        );
+
+    // Bytes, larger request bodies are rejected with 413
+    let max_body_size = env::var("MAX_BODY_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_SIZE);
+
     let app = Route::new()
         .at("/openapi.json", service.clone().spec_endpoint())
-        .nest("/", service.data(dataset));
+        .at("/openapi.yaml", service.clone().spec_endpoint_yaml())
+        .nest("/docs", service.swagger_ui())
+        .at("/metrics", get(metrics::metrics).data(dataset.clone()))
+        .nest(
+            "/",
+            service
+                .around(jsonp::wrap_callback)
+                .around(caching::cache_headers)
+                .around(auth::require_api_token)
+                .around(metrics::count_requests)
+                .around(ratelimit::limit_requests)
+                .around(move |ep, req| limit_body_size(ep, req, max_body_size))
+                .data(dataset)
+                .data(updates)
+                .data(provider)
+                .data(last_update),
+        )
+        // Compress responses for clients that support it, mostly for large timeframes
+        .with(Compression::new())
+        .with(cors)
+        .around(|ep, mut req| async move {
+            // Compression buffers the output, which would hold back the events
+            if req.uri().path() == "/rates/stream" {
+                req.headers_mut().remove(header::ACCEPT_ENCODING);
+            }
+
+            ep.call(req).await
+        })
+        // Outermost, so that the latency includes the rest of the middleware
+        .around(logging::log_requests);
+
+    // Seconds, after which the remaining connections are closed
+    let shutdown_timeout = env::var("SHUTDOWN_TIMEOUT")
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs);
+
+    log::info!("Listening on {}", socket_addr);
 
-    let socket_addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 8000);
     poem::Server::new(TcpListener::bind(socket_addr))
-        .run_with_graceful_shutdown(app, async move { ctrl_c().await.unwrap_or(()) }, None)
+        .run_with_graceful_shutdown(app, shutdown_signal(), Some(shutdown_timeout))
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use poem::{handler, Body};
+
+    use super::*;
+
+    #[handler]
+    fn echo(body: String) -> String {
+        body
+    }
+
+    async fn post(body: Body, content_length: Option<usize>) -> StatusCode {
+        let mut req = Request::builder().method(Method::POST);
+        req = match content_length {
+            Some(length) => req.header(header::CONTENT_LENGTH, length),
+            None => req.header(header::TRANSFER_ENCODING, "chunked"),
+        };
+
+        match limit_body_size(Arc::new(echo), req.body(body), 8).await {
+            Ok(resp) => resp.status(),
+            Err(err) => err.status(),
+        }
+    }
+
+    fn chunked(chunks: &'static [&'static str]) -> Body {
+        let chunks =
+            futures::stream::iter(chunks.iter().map(|chunk| Ok::<_, std::io::Error>(*chunk)));
+
+        Body::from_bytes_stream(chunks)
+    }
+
+    #[tokio::test]
+    async fn chunked_bodies_are_read_up_to_the_limit() {
+        assert_eq!(post(chunked(&["{}", "{}"]), None).await, StatusCode::OK);
+        assert_eq!(
+            post(chunked(&["{}", "{}{}", "{}"]), None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post(chunked(&["{}{}", "{}{}", "{"]), None).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn sized_bodies_are_checked_by_their_length() {
+        assert_eq!(post(Body::from("{}"), Some(2)).await, StatusCode::OK);
+        assert_eq!(
+            post(Body::from("{}{}{}{}{}"), Some(10)).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
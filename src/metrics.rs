@@ -0,0 +1,93 @@
+use std::sync::{Arc, LazyLock};
+
+use poem::{handler, web::Data, Endpoint, IntoResponse, Request, Response};
+use poem_openapi::OperationId;
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, Encoder, IntCounter,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+
+use crate::data::{self, SharedDataset};
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "exchangerates_requests_total",
+        "Number of requests per endpoint",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static FAILED_DOWNLOADS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "exchangerates_failed_downloads_total",
+        "Number of failed dataset downloads"
+    )
+    .unwrap()
+});
+
+static DATASET_AGE: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "exchangerates_dataset_age_seconds",
+        "Seconds since the start of the latest day in the dataset"
+    )
+    .unwrap()
+});
+
+static CURRENCIES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "exchangerates_currencies",
+        "Number of currencies in the dataset"
+    )
+    .unwrap()
+});
+
+static DAYS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("exchangerates_days", "Number of days in the dataset").unwrap()
+});
+
+/// Counts requests per API operation. Requests which did not match
+/// any operation are not counted to keep the number of labels bounded
+pub async fn count_requests<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    let res = ep.call(req).await.map(IntoResponse::into_response);
+
+    let operation = match &res {
+        Ok(resp) => resp.data::<OperationId>(),
+        Err(err) => err.data::<OperationId>(),
+    };
+
+    if let Some(OperationId(operation)) = operation {
+        REQUESTS.with_label_values(&[operation]).inc();
+    }
+
+    res
+}
+
+/// Exposes the metrics in the Prometheus text format
+#[handler]
+pub async fn metrics(dataset: Data<&SharedDataset>) -> poem::Result<String> {
+    {
+        let dataset = data::snapshot(&dataset).await;
+
+        // The dataset gauges are cheap to compute, so we do it on every scrape
+        CURRENCIES.set(dataset.currencies.len() as i64);
+        DAYS.set(dataset.days.len() as i64);
+
+        if let Some(day) = dataset.days.last() {
+            let start = day.date.and_hms_opt(0, 0, 0).unwrap_or_default();
+            let now = data::now().naive_local();
+
+            DATASET_AGE.set((now - start).num_seconds());
+        }
+    }
+
+    // Make sure the counter shows up even before the first failure
+    LazyLock::force(&FAILED_DOWNLOADS);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(poem::error::InternalServerError)?;
+
+    String::from_utf8(buffer).map_err(poem::error::InternalServerError)
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::IpAddr,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use poem::{http::header, Endpoint, IntoResponse, Request, Response};
+use reqwest::StatusCode;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Clients are only pruned once there are this many of them. Pruning
+/// evicts the oldest windows down to half of it, so it happens rarely
+const MAX_CLIENTS: usize = 10_000;
+
+/// Requests per minute per client, no limiting when unset
+static RATE_LIMIT: LazyLock<Option<u32>> = LazyLock::new(|| {
+    env::var("RATE_LIMIT")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+        .filter(|limit| *limit > 0)
+});
+
+/// Only trust `X-Forwarded-For` when running behind a proxy that sets it,
+/// otherwise clients could pick any address they like
+static TRUST_FORWARDED_FOR: LazyLock<bool> = LazyLock::new(|| {
+    env::var("TRUST_FORWARDED_FOR").is_ok_and(|s| matches!(s.as_str(), "1" | "true"))
+});
+
+/// When every client's current window started and how many requests it made since
+static WINDOWS: LazyLock<Mutex<HashMap<IpAddr, (Instant, u32)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn client_ip(req: &Request) -> Option<IpAddr> {
+    if *TRUST_FORWARDED_FOR {
+        // The last address is the one our proxy saw, anything before it
+        // comes from the client and could be made up
+        let forwarded = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+
+    req.remote_addr().as_socket_addr().map(|addr| addr.ip())
+}
+
+/// Forgets the expired windows and, when that is not enough, the oldest ones,
+/// which resets those clients' counts
+fn prune(windows: &mut HashMap<IpAddr, (Instant, u32)>, now: Instant) {
+    windows.retain(|_, (start, _)| now - *start < WINDOW);
+
+    let keep = MAX_CLIENTS / 2;
+    if windows.len() > keep {
+        let mut starts = windows
+            .values()
+            .map(|(start, _)| *start)
+            .collect::<Vec<_>>();
+        let (_, oldest_kept, _) = starts.select_nth_unstable(windows.len() - keep);
+        let oldest_kept = *oldest_kept;
+
+        windows.retain(|_, (start, _)| *start >= oldest_kept);
+    }
+}
+
+/// Counts the request against the client's window, returning how long
+/// the client has to wait when it is over the limit
+fn over_limit(ip: IpAddr, limit: u32) -> Option<Duration> {
+    let now = Instant::now();
+    let mut windows = WINDOWS.lock().unwrap_or_else(|e| e.into_inner());
+
+    if windows.len() >= MAX_CLIENTS && !windows.contains_key(&ip) {
+        prune(&mut windows, now);
+    }
+
+    let (start, count) = windows.entry(ip).or_insert((now, 0));
+
+    if now - *start >= WINDOW {
+        *start = now;
+        *count = 0;
+    }
+
+    if *count >= limit {
+        return Some(WINDOW - (now - *start));
+    }
+
+    *count += 1;
+    None
+}
+
+/// Limits every client to `RATE_LIMIT` requests per minute
+pub async fn limit_requests<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    if let (Some(limit), Some(ip)) = (*RATE_LIMIT, client_ip(&req)) {
+        if let Some(retry_after) = over_limit(ip, limit) {
+            // Round up, so that clients do not retry a moment too early
+            let retry_after = retry_after.as_secs() + 1;
+
+            return Ok(StatusCode::TOO_MANY_REQUESTS
+                .with_header(header::RETRY_AFTER, retry_after)
+                .into_response());
+        }
+    }
+
+    ep.call(req).await.map(IntoResponse::into_response)
+}
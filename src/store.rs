@@ -0,0 +1,225 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::NaiveDate;
+use futures::future::BoxFuture;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::data::{self, Dataset, Day, RateProvider, SharedProvider};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS rates (
+        date TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        rate REAL,
+        PRIMARY KEY (date, currency)
+    );
+";
+
+/// Keeps the dataset of another provider in SQLite (at `SQLITE_PATH`), so that
+/// cold starts do not have to download or parse anything. Updates only append
+/// the days the database does not have yet
+pub struct Sqlite {
+    connection: Mutex<Connection>,
+    provider: SharedProvider,
+}
+
+impl Sqlite {
+    pub fn open(path: &Path, provider: SharedProvider) -> anyhow::Result<Self> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            provider,
+        })
+    }
+
+    /// Builds the dataset from the database, `None` when it is empty
+    /// or was filled with different settings
+    fn load(&self) -> anyhow::Result<Option<Dataset>> {
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+
+        if stored_settings(&connection)? != Some(data::parse_settings()) {
+            return Ok(None);
+        }
+
+        let mut currencies = connection
+            .prepare("SELECT DISTINCT currency FROM rates")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        currencies.sort();
+
+        let mut days = Vec::<Day>::new();
+        let mut statement =
+            connection.prepare("SELECT date, currency, rate FROM rates ORDER BY date")?;
+        let mut rows = statement.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let date: NaiveDate = row.get(0)?;
+            let currency: String = row.get(1)?;
+
+            if days.last().is_none_or(|day| day.date != date) {
+                days.push(Day {
+                    date,
+                    rates: vec![None; currencies.len()],
+                });
+            }
+
+            // Unwrapping is safe because the currencies come from the same table
+            let index = currencies.binary_search(&currency).unwrap();
+            if let Some(day) = days.last_mut() {
+                day.rates[index] = row.get(2)?;
+            }
+        }
+
+        if days.is_empty() {
+            return Ok(None);
+        }
+
+        log::info!("Loaded {} days from SQLite", days.len());
+
+        Ok(Some(Dataset::new(
+            days,
+            data::shared_currencies(currencies),
+        )))
+    }
+
+    /// Appends the days newer than the stored ones. Everything is replaced
+    /// when the settings changed, since the stored rates no longer apply
+    fn save(&self, dataset: &Dataset) -> anyhow::Result<usize> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let transaction = connection.transaction()?;
+
+        let settings = data::parse_settings();
+        let last_date = match stored_settings(&transaction)? {
+            Some(stored) if stored == settings => {
+                transaction.query_row("SELECT MAX(date) FROM rates", [], |row| {
+                    row.get::<_, Option<NaiveDate>>(0)
+                })?
+            }
+            _ => {
+                transaction.execute("DELETE FROM rates", [])?;
+                transaction.execute(
+                    "INSERT OR REPLACE INTO settings (key, value) VALUES ('parse', ?1)",
+                    params![settings],
+                )?;
+                None
+            }
+        };
+
+        let mut appended = 0;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO rates (date, currency, rate) VALUES (?1, ?2, ?3)",
+            )?;
+
+            for day in dataset.days.iter() {
+                if last_date.is_some_and(|last_date| day.date <= last_date) {
+                    continue;
+                }
+
+                for (currency, rate) in dataset.currencies.iter().zip(day.rates.iter()) {
+                    statement.execute(params![day.date, currency, rate])?;
+                }
+                appended += 1;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(appended)
+    }
+}
+
+fn stored_settings(connection: &Connection) -> rusqlite::Result<Option<String>> {
+    connection
+        .query_row(
+            "SELECT value FROM settings WHERE key = 'parse'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+impl RateProvider for Sqlite {
+    fn fetch(&self) -> BoxFuture<'_, anyhow::Result<Dataset>> {
+        Box::pin(async {
+            let dataset = self.provider.fetch().await?;
+
+            // The database is only a cache, the dataset is still good without it
+            match tokio::task::block_in_place(|| self.save(&dataset)) {
+                Ok(appended) => log::info!("Appended {} days to SQLite", appended),
+                Err(e) => log::warn!("Could not store dataset in SQLite: {}", e),
+            }
+
+            Ok(dataset)
+        })
+    }
+
+    fn fetch_update<'a>(&'a self, current: &'a Dataset) -> BoxFuture<'a, anyhow::Result<Dataset>> {
+        Box::pin(async {
+            let dataset = self.provider.fetch_update(current).await?;
+
+            match tokio::task::block_in_place(|| self.save(&dataset)) {
+                Ok(appended) => log::info!("Appended {} days to SQLite", appended),
+                Err(e) => log::warn!("Could not store dataset in SQLite: {}", e),
+            }
+
+            Ok(dataset)
+        })
+    }
+
+    fn source(&self) -> String {
+        self.provider.source()
+    }
+
+    fn cached(&self) -> BoxFuture<'_, anyhow::Result<Option<Dataset>>> {
+        Box::pin(async {
+            match tokio::task::block_in_place(|| self.load()) {
+                Ok(Some(dataset)) => return Ok(Some(dataset)),
+                Ok(None) => {}
+                Err(e) => log::warn!("Could not load dataset from SQLite: {}", e),
+            }
+
+            // Fill the database from the provider's own cache, if it has one
+            let dataset = self.provider.cached().await?;
+            if let Some(dataset) = dataset.as_ref() {
+                if let Err(e) = tokio::task::block_in_place(|| self.save(dataset)) {
+                    log::warn!("Could not store dataset in SQLite: {}", e);
+                }
+            }
+
+            Ok(dataset)
+        })
+    }
+}
+
+/// Wraps the provider in SQLite when `SQLITE_PATH` is set
+pub fn wrap(provider: SharedProvider) -> SharedProvider {
+    let Some(path) = env::var_os("SQLITE_PATH").map(PathBuf::from) else {
+        return provider;
+    };
+
+    match Sqlite::open(&path, provider.clone()) {
+        Ok(sqlite) => {
+            log::info!("Storing the dataset in {}", path.display());
+            Arc::new(sqlite)
+        }
+        Err(e) => {
+            log::warn!("Could not open {}, not using SQLite: {}", path.display(), e);
+            provider
+        }
+    }
+}